@@ -1,16 +1,11 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use fun_with_flags::{enable, enabled};
 
-#[cfg(feature = "postgres-backend")]
+#[cfg(all(feature = "postgres-backend", not(feature = "redis-backend")))]
 mod postgres_test_context {
-    use fun_with_flags;
-    use postgres::NoTls;
+    use postgres::{Client, NoTls};
 
-    use r2d2_postgres::PostgresConnectionManager;
-
-    type PostgresClient = r2d2::PooledConnection<PostgresConnectionManager<NoTls>>;
-
-    const MAIN_DATABASE: &'static str = "postgres";
+    const MAIN_DATABASE: &str = "postgres";
 
     pub struct TestContext {
         is_dropped: bool,
@@ -19,12 +14,12 @@ mod postgres_test_context {
     impl TestContext {
         pub fn new() -> Self {
             println!("Set up resources");
-            let conn = fun_with_flags::establish_connection_to_database(MAIN_DATABASE);
-            Self::create_db(&conn);
+            let mut client = Self::get_client(MAIN_DATABASE);
+            Self::create_db(&mut client);
 
-            let conn = fun_with_flags::establish_connection().unwrap();
+            let mut client = Self::get_client("fun_with_flags_repo");
 
-            Self::migrate(&conn);
+            Self::migrate(&mut client);
             println!("Set up resources, done");
 
             Self { is_dropped: false }
@@ -32,25 +27,25 @@ mod postgres_test_context {
 
         fn clean(&mut self) {
             println!("Clean up resources");
-            let conn = fun_with_flags::establish_connection_to_database(MAIN_DATABASE);
-            Self::drop_db(&conn);
+            fun_with_flags::default_client()
+                .backend()
+                .clear_all()
+                .unwrap();
 
-            cfg_if::cfg_if! {
-                if #[cfg(feature = "cached")] {
-                    fun_with_flags::backend::cached::flush_cache()
-                }
-            }
+            let mut client = Self::get_client(MAIN_DATABASE);
+            Self::drop_db(&mut client);
 
             self.is_dropped = true;
         }
 
-        fn get_client(conn: &fun_with_flags::DBConnection) -> PostgresClient {
-            fun_with_flags::Backend::create_conn(conn).unwrap()
-        }
+        fn get_client(database_name: &str) -> Client {
+            let config = fun_with_flags::config::fetch_config().unwrap();
+            let (database_url, _name) = config.parts().expect("DATABASE_ADDRESS must be set");
 
-        fn drop_db(conn: &fun_with_flags::DBConnection) {
-            let mut client = Self::get_client(conn);
+            Client::connect(&format!("{}/{}", database_url, database_name), NoTls).unwrap()
+        }
 
+        fn drop_db(client: &mut Client) {
             client.batch_execute("SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = 'fun_with_flags_repo';")
                 .unwrap();
             client
@@ -58,17 +53,15 @@ mod postgres_test_context {
                 .expect("Couldn't drop database fun_with_flags_repo");
         }
 
-        fn create_db(conn: &fun_with_flags::DBConnection) {
-            if Self::database_not_exists(conn) {
-                let mut client = Self::get_client(conn);
+        fn create_db(client: &mut Client) {
+            if Self::database_not_exists(client) {
                 client
                     .batch_execute("CREATE DATABASE fun_with_flags_repo;")
                     .unwrap();
             }
         }
 
-        fn database_not_exists(conn: &fun_with_flags::DBConnection) -> bool {
-            let mut client = Self::get_client(conn);
+        fn database_not_exists(client: &mut Client) -> bool {
             client
                 .query(
                     "SELECT 1 as column from pg_database where datname = 'fun_with_flags_repo';",
@@ -78,18 +71,16 @@ mod postgres_test_context {
                 .is_empty()
         }
 
-        fn table_not_exists(conn: &fun_with_flags::DBConnection) -> bool {
-            let mut client = Self::get_client(conn);
+        fn table_not_exists(client: &mut Client) -> bool {
             client
                 .query("SELECT 1 as column FROM information_schema.tables WHERE table_name = 'fun_with_flags_toggles';", &[])
                 .unwrap()
                 .is_empty()
         }
 
-        fn migrate(conn: &fun_with_flags::DBConnection) {
-            let mut client = Self::get_client(conn);
+        fn migrate(client: &mut Client) {
             let migration = include_str!("../migrations/postgres/up.sql");
-            if Self::table_not_exists(conn) {
+            if Self::table_not_exists(client) {
                 client.batch_execute(migration).unwrap();
             }
         }
@@ -110,9 +101,10 @@ mod redis_test_context {
 
     impl TestContext {
         pub fn new() -> Self {
-            let db = fun_with_flags::establish_connection().unwrap();
-
-            fun_with_flags::Backend::clean_all(&db).unwrap();
+            fun_with_flags::default_client()
+                .backend()
+                .clear_all()
+                .unwrap();
 
             TestContext {}
         }
@@ -120,15 +112,10 @@ mod redis_test_context {
 
     impl Drop for TestContext {
         fn drop(&mut self) {
-            let db = fun_with_flags::establish_connection().unwrap();
-
-            fun_with_flags::Backend::clean_all(&db).unwrap();
-
-            cfg_if::cfg_if! {
-                if #[cfg(feature = "cached")] {
-                    fun_with_flags::backend::cached::flush_cache()
-                }
-            }
+            fun_with_flags::default_client()
+                .backend()
+                .clear_all()
+                .unwrap();
         }
     }
 }
@@ -157,12 +144,12 @@ cfg_if::cfg_if! {
 
 fn enabling() -> bool {
     enable("oke").unwrap();
-    enabled("oke") == true
+    enabled("oke")
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
     let mut _ctx = TestContext::new();
-    c.bench_function("enabled", |b| b.iter(|| enabling()));
+    c.bench_function("enabled", |b| b.iter(enabling));
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1 @@
+DROP TABLE fun_with_flags_toggles;
\ No newline at end of file
@@ -0,0 +1,9 @@
+CREATE TABLE fun_with_flags_toggles (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    flag_name VARCHAR(255) NOT NULL,
+    gate_type VARCHAR(255) NOT NULL,
+    target VARCHAR(255) NOT NULL,
+    enabled BOOLEAN NOT NULL
+);
+
+CREATE UNIQUE INDEX fwf_flag_name_gate_target_idx ON fun_with_flags_toggles (flag_name, gate_type, target);
@@ -0,0 +1,2 @@
+DROP TRIGGER fun_with_flags_toggles_changed ON fun_with_flags_toggles;
+DROP FUNCTION fun_with_flags_notify_change();
\ No newline at end of file
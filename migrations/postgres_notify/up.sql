@@ -0,0 +1,14 @@
+CREATE FUNCTION "fun_with_flags_notify_change"() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        PERFORM pg_notify('fun_with_flags_changes', OLD.flag_name);
+    ELSE
+        PERFORM pg_notify('fun_with_flags_changes', NEW.flag_name);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER "fun_with_flags_toggles_changed"
+AFTER INSERT OR UPDATE OR DELETE ON "fun_with_flags_toggles"
+FOR EACH ROW EXECUTE FUNCTION "fun_with_flags_notify_change"();
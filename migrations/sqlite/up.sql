@@ -0,0 +1,9 @@
+CREATE TABLE fun_with_flags_toggles (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    flag_name TEXT NOT NULL,
+    gate_type TEXT NOT NULL,
+    target TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL
+);
+
+CREATE UNIQUE INDEX fwf_flag_name_gate_target_idx ON fun_with_flags_toggles (flag_name, gate_type, target);
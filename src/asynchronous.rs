@@ -0,0 +1,691 @@
+//! Async version of the client and the free functions, enable it with the `async` feature.
+//!
+//! Uses the same storage format as the sync api, so async and sync processes can share the flags.
+
+use crate::backend::{self, null, AsyncStorageBackend};
+use crate::config::{fetch_config, BackendType, CacheConfig, DefaultsConfig, RawConfig};
+use crate::error::ErrorCallback;
+use crate::functions::config_defaults;
+use crate::models::GroupSet;
+use crate::overrides::{Evaluation, Layer, Overrides};
+use crate::{Actor, Error, FeatureFlag, Flag, Group, Output};
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+lazy_static::lazy_static! {
+    static ref DEFAULT_CLIENT: RwLock<Option<Arc<AsyncFunWithFlags>>> = RwLock::new(None);
+}
+
+/// Async client that owns the backend (and with that the pool and cache).
+pub struct AsyncFunWithFlags {
+    backend: Box<dyn AsyncStorageBackend>,
+    overrides: Overrides,
+    defaults: DefaultsConfig,
+    on_error: Option<ErrorCallback>,
+}
+
+/// Builder for the `AsyncFunWithFlags` client.
+///
+/// If no url or config is given, the config is read from `fun-with-flags.toml` and the environment.
+#[derive(Default)]
+pub struct AsyncFunWithFlagsBuilder {
+    url: Option<String>,
+    backend_type: Option<BackendType>,
+    config: Option<RawConfig>,
+    cache: Option<CacheConfig>,
+    overrides: Option<Overrides>,
+    defaults: Option<DefaultsConfig>,
+    on_error: Option<ErrorCallback>,
+}
+
+impl AsyncFunWithFlagsBuilder {
+    pub fn url<S: Into<String>>(mut self, url: S) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// backend to use with `url`, defaults to `BackendType::Auto`
+    pub fn backend_type(mut self, backend_type: BackendType) -> Self {
+        self.backend_type = Some(backend_type);
+        self
+    }
+
+    pub fn config(mut self, config: RawConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// settings of the cache, overrides the `[cache]` section of the config
+    pub fn cache(mut self, cache: CacheConfig) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// overrides that are checked before the backend, instead of the ones from the `[overrides]` section of the config
+    pub fn overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = Some(overrides);
+        self
+    }
+
+    /// values of the flags when the backend fails, instead of the `[defaults]` section of the config
+    pub fn defaults(mut self, defaults: DefaultsConfig) -> Self {
+        self.defaults = Some(defaults);
+        self
+    }
+
+    /// called when a flag falls back to its default, by default the error is logged as a warning
+    pub fn on_error<F: Fn(&str, &Error) + Send + Sync + 'static>(mut self, on_error: F) -> Self {
+        self.on_error = Some(Box::new(on_error));
+        self
+    }
+
+    pub async fn build(self) -> Result<AsyncFunWithFlags, Error> {
+        let backend_type = self.backend_type.unwrap_or(BackendType::Auto);
+        let config = match (&self.url, self.config) {
+            (None, None) => Some(fetch_config()?),
+            (_, config) => config,
+        };
+
+        let overrides = match self.overrides {
+            Some(overrides) => overrides,
+            None => Overrides::load(
+                &config
+                    .as_ref()
+                    .map(RawConfig::overrides)
+                    .unwrap_or_default(),
+            )?,
+        };
+
+        let defaults = match self.defaults {
+            Some(defaults) => defaults,
+            None => config.as_ref().map(RawConfig::defaults).unwrap_or_default(),
+        };
+
+        let backend = match (self.url, config) {
+            (Some(url), _) => {
+                backend::establish_async_with_cache(
+                    backend_type,
+                    &url,
+                    self.cache.unwrap_or_default(),
+                )
+                .await?
+            }
+            (None, Some(mut config)) => {
+                if let Some(cache) = self.cache {
+                    config.cache_config = Some(cache);
+                }
+                backend::from_config_async(&config).await?
+            }
+            (None, None) => unreachable!("the config is fetched when there is no url"),
+        };
+
+        Ok(AsyncFunWithFlags {
+            backend,
+            overrides,
+            defaults,
+            on_error: self.on_error,
+        })
+    }
+}
+
+impl AsyncFunWithFlags {
+    /// creates a client from `fun-with-flags.toml` and the `DATABASE_*` environment variables
+    pub async fn new() -> Result<AsyncFunWithFlags, Error> {
+        Self::builder().build().await
+    }
+
+    pub fn builder() -> AsyncFunWithFlagsBuilder {
+        AsyncFunWithFlagsBuilder::default()
+    }
+
+    pub async fn from_config(config: RawConfig) -> Result<AsyncFunWithFlags, Error> {
+        Self::builder().config(config).build().await
+    }
+
+    pub fn with_backend<B: AsyncStorageBackend + 'static>(backend: B) -> AsyncFunWithFlags {
+        AsyncFunWithFlags {
+            backend: Box::new(backend),
+            overrides: Overrides::new(),
+            defaults: DefaultsConfig::default(),
+            on_error: None,
+        }
+    }
+
+    /// replaces the overrides that are checked before the backend
+    pub fn with_overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// replaces the values of the flags when the backend fails
+    pub fn with_defaults(mut self, defaults: DefaultsConfig) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    /// replaces the callback for errors of flags that fall back to their default
+    pub fn with_error_callback<F: Fn(&str, &Error) + Send + Sync + 'static>(
+        mut self,
+        on_error: F,
+    ) -> Self {
+        self.on_error = Some(Box::new(on_error));
+        self
+    }
+
+    pub fn backend(&self) -> &dyn AsyncStorageBackend {
+        self.backend.as_ref()
+    }
+
+    pub fn overrides(&self) -> &Overrides {
+        &self.overrides
+    }
+
+    pub async fn enable(&self, flag: &str) -> Output {
+        self.set(FeatureFlag::Boolean {
+            name: flag.to_string(),
+            enabled: true,
+        })
+        .await
+    }
+
+    pub async fn enable_for<T: Actor>(&self, flag: &str, actor: &T) -> Output {
+        self.set(FeatureFlag::Actor {
+            name: flag.to_string(),
+            target: actor.feature_flag_id(),
+            enabled: true,
+        })
+        .await
+    }
+
+    pub async fn disable(&self, flag: &str) -> Output {
+        self.set(FeatureFlag::Boolean {
+            name: flag.to_string(),
+            enabled: false,
+        })
+        .await
+    }
+
+    pub async fn disable_for<T: Actor>(&self, flag: &str, actor: &T) -> Output {
+        self.set(FeatureFlag::Actor {
+            name: flag.to_string(),
+            target: actor.feature_flag_id(),
+            enabled: false,
+        })
+        .await
+    }
+
+    /// replaces the settings of the cache at runtime, fails when the cached feature is not enabled
+    pub fn configure_cache(&self, cache: CacheConfig) -> Result<(), Error> {
+        self.backend.configure_cache(cache)
+    }
+
+    /// loads the flag with all its gates, a flag that is not stored has no gates
+    pub async fn get_flag(&self, flag: &str) -> Result<Flag, Error> {
+        self.backend.get_flag(flag).await
+    }
+
+    /// names of all the stored flags
+    pub async fn all_flag_names(&self) -> Result<HashSet<String>, Error> {
+        self.backend.list().await
+    }
+
+    /// all the stored flags with all their gates, sorted by name
+    pub async fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        self.backend.all_flags().await
+    }
+
+    /// checks the overrides, then the boolean gate and if that is not enabled the percentage of time gate
+    ///
+    /// When the backend fails the flag falls back to its default, use `try_enabled` to get the error.
+    pub async fn enabled(&self, flag: &str) -> bool {
+        let default = self.defaults.enabled(flag);
+        self.enabled_or(flag, default).await
+    }
+
+    /// like `enabled`, but returns `default` when the backend fails
+    pub async fn enabled_or(&self, flag: &str, default: bool) -> bool {
+        match self.evaluate(flag).await {
+            Ok(evaluation) => evaluation.enabled,
+            Err(e) => self.fall_back(flag, e, default),
+        }
+    }
+
+    /// like `enabled`, but returns the error when the backend fails
+    pub async fn try_enabled(&self, flag: &str) -> Result<bool, Error> {
+        self.evaluate(flag)
+            .await
+            .map(|evaluation| evaluation.enabled)
+    }
+
+    /// like `enabled`, but also tells which layer decided, an override or the backend
+    pub async fn evaluate(&self, flag: &str) -> Result<Evaluation, Error> {
+        if let Some(evaluation) = self.overrides.evaluate(flag) {
+            return Ok(evaluation);
+        }
+
+        let flag = self.backend.get_flag(flag).await?;
+        flag.check_decoded(self.defaults.decode_policy())?;
+        Ok(Evaluation {
+            enabled: flag.enabled(),
+            layer: Layer::Backend,
+        })
+    }
+
+    pub async fn disabled(&self, flag: &str) -> bool {
+        !self.enabled(flag).await
+    }
+
+    /// checks the overrides, then the gates in the same order as the sync client, see `Flag::enabled_for`
+    ///
+    /// When the backend fails the flag falls back to its default, use `try_enabled_for` to get the error.
+    pub async fn enabled_for<T: Actor + Group>(&self, flag: &str, actor: &T) -> bool {
+        let default = self.defaults.enabled(flag);
+        self.enabled_for_or(flag, actor, default).await
+    }
+
+    /// like `enabled_for`, but returns `default` when the backend fails
+    pub async fn enabled_for_or<T: Actor + Group>(
+        &self,
+        flag: &str,
+        actor: &T,
+        default: bool,
+    ) -> bool {
+        match self.evaluate_for(flag, actor).await {
+            Ok(evaluation) => evaluation.enabled,
+            Err(e) => self.fall_back(flag, e, default),
+        }
+    }
+
+    /// like `enabled_for`, but returns the error when the backend fails
+    pub async fn try_enabled_for<T: Actor + Group>(
+        &self,
+        flag: &str,
+        actor: &T,
+    ) -> Result<bool, Error> {
+        self.evaluate_for(flag, actor)
+            .await
+            .map(|evaluation| evaluation.enabled)
+    }
+
+    /// reports the error of the backend and returns the default
+    fn fall_back(&self, flag: &str, error: Error, default: bool) -> bool {
+        match &self.on_error {
+            Some(on_error) => on_error(flag, &error),
+            None => log::warn!(
+                "flag {} is {} by default, the backend failed: {}",
+                flag,
+                if default { "enabled" } else { "disabled" },
+                error
+            ),
+        }
+
+        default
+    }
+
+    /// like `enabled_for`, but also tells which layer decided, an override or the backend
+    pub async fn evaluate_for<T: Actor + Group>(
+        &self,
+        flag: &str,
+        actor: &T,
+    ) -> Result<Evaluation, Error> {
+        if let Some(evaluation) = self.overrides.evaluate_for(flag, actor) {
+            return Ok(evaluation);
+        }
+
+        let flag = self.backend.get_flag(flag).await?;
+        flag.check_decoded(self.defaults.decode_policy())?;
+        Ok(Evaluation {
+            enabled: flag.enabled_for(actor),
+            layer: Layer::Backend,
+        })
+    }
+
+    pub async fn disabled_for<T: Actor + Group>(&self, flag: &str, actor: &T) -> bool {
+        !self.enabled_for(flag, actor).await
+    }
+
+    /// validates the gate before the backend stores it
+    async fn set(&self, flag: FeatureFlag) -> Output {
+        flag.validate()?;
+        self.backend.set(flag).await
+    }
+
+    /// removes all the gates of the flag
+    pub async fn clear(&self, flag: &str) -> Result<(), Error> {
+        self.backend.clear(flag).await
+    }
+
+    /// removes the actor gate, the actor falls back to the other gates
+    pub async fn clear_for<T: Actor>(&self, flag: &str, actor: &T) -> Result<(), Error> {
+        self.backend
+            .clear_gate(FeatureFlag::Actor {
+                name: flag.to_string(),
+                target: actor.feature_flag_id(),
+                enabled: false,
+            })
+            .await
+    }
+
+    /// removes the gate of one group
+    pub async fn clear_for_group(&self, flag: &str, group_name: &str) -> Result<(), Error> {
+        self.backend
+            .clear_gate(FeatureFlag::Group {
+                name: flag.to_string(),
+                target: GroupSet::new(group_name.to_string()),
+                enabled: false,
+            })
+            .await
+    }
+
+    /// removes the boolean gate
+    pub async fn clear_boolean(&self, flag: &str) -> Result<(), Error> {
+        self.backend
+            .clear_gate(FeatureFlag::Boolean {
+                name: flag.to_string(),
+                enabled: false,
+            })
+            .await
+    }
+
+    /// removes the percentage gate, both of time and of actors
+    pub async fn clear_percentage(&self, flag: &str) -> Result<(), Error> {
+        self.backend
+            .clear_gate(FeatureFlag::Percentage {
+                name: flag.to_string(),
+                target: 0.0,
+                enabled: false,
+            })
+            .await
+    }
+
+    pub async fn enable_percentage_of_time(&self, flag: &str, percentage: f64) -> Output {
+        self.set(FeatureFlag::Time {
+            name: flag.to_string(),
+            target: percentage,
+            enabled: true,
+        })
+        .await
+    }
+
+    pub async fn disable_percentage_of_time(&self, flag: &str) -> Output {
+        self.set(FeatureFlag::Time {
+            name: flag.to_string(),
+            target: 0.0,
+            enabled: false,
+        })
+        .await
+    }
+
+    pub async fn enable_percentage_of_actors(&self, flag: &str, percentage: f64) -> Output {
+        self.set(FeatureFlag::Percentage {
+            name: flag.to_string(),
+            target: percentage,
+            enabled: true,
+        })
+        .await
+    }
+
+    pub async fn disable_percentage_of_actors(&self, flag: &str) -> Output {
+        self.set(FeatureFlag::Percentage {
+            name: flag.to_string(),
+            target: 0.0,
+            enabled: false,
+        })
+        .await
+    }
+
+    pub async fn enable_for_group(&self, flag: &str, group_name: &str) -> Output {
+        self.set(FeatureFlag::Group {
+            name: flag.to_string(),
+            target: GroupSet::new(group_name.to_string()),
+            enabled: true,
+        })
+        .await
+    }
+
+    pub async fn disable_for_group(&self, flag: &str, group_name: &str) -> Output {
+        self.set(FeatureFlag::Group {
+            name: flag.to_string(),
+            target: GroupSet::new(group_name.to_string()),
+            enabled: false,
+        })
+        .await
+    }
+}
+
+/// the client used by the async free functions, created from the config on first use
+///
+/// When the client can not be created the error is logged and every flag uses its default, same as the sync `default_client`.
+/// The `try_*`, `enable_*`, `disable_*` and `clear_*` functions return the error instead.
+pub async fn default_client() -> Arc<AsyncFunWithFlags> {
+    if let Some(client) = DEFAULT_CLIENT.read().await.as_ref() {
+        return client.clone();
+    }
+
+    let mut default_client = DEFAULT_CLIENT.write().await;
+    if let Some(client) = default_client.as_ref() {
+        return client.clone();
+    }
+
+    let client = Arc::new(AsyncFunWithFlags::new().await.unwrap_or_else(|e| {
+        log::error!(
+            "can not create the default client, flags use their defaults: {}",
+            e
+        );
+        AsyncFunWithFlags::with_backend(null::Unavailable::new(&e)).with_defaults(config_defaults())
+    }));
+    *default_client = Some(client.clone());
+    client
+}
+
+/// replaces the client used by the async free functions
+pub async fn set_default_client(client: AsyncFunWithFlags) {
+    *DEFAULT_CLIENT.write().await = Some(Arc::new(client));
+}
+
+pub async fn configure_cache(cache: CacheConfig) -> Result<(), Error> {
+    default_client().await.configure_cache(cache)
+}
+
+pub async fn get_flag(flag: &str) -> Result<Flag, Error> {
+    default_client().await.get_flag(flag).await
+}
+
+pub async fn all_flag_names() -> Result<HashSet<String>, Error> {
+    default_client().await.all_flag_names().await
+}
+
+pub async fn all_flags() -> Result<Vec<Flag>, Error> {
+    default_client().await.all_flags().await
+}
+
+pub async fn enable(flag: &str) -> Output {
+    default_client().await.enable(flag).await
+}
+
+pub async fn enable_for<T: Actor>(flag: &str, actor: &T) -> Output {
+    default_client().await.enable_for(flag, actor).await
+}
+
+pub async fn disable(flag: &str) -> Output {
+    default_client().await.disable(flag).await
+}
+
+pub async fn disable_for<T: Actor>(flag: &str, actor: &T) -> Output {
+    default_client().await.disable_for(flag, actor).await
+}
+
+pub async fn enabled(flag: &str) -> bool {
+    default_client().await.enabled(flag).await
+}
+
+pub async fn try_enabled(flag: &str) -> Result<bool, Error> {
+    default_client().await.try_enabled(flag).await
+}
+
+pub async fn evaluate(flag: &str) -> Result<Evaluation, Error> {
+    default_client().await.evaluate(flag).await
+}
+
+pub async fn enabled_or(flag: &str, default: bool) -> bool {
+    default_client().await.enabled_or(flag, default).await
+}
+
+pub async fn disabled(flag: &str) -> bool {
+    default_client().await.disabled(flag).await
+}
+
+pub async fn enabled_for<T: Actor + Group>(flag: &str, actor: &T) -> bool {
+    default_client().await.enabled_for(flag, actor).await
+}
+
+pub async fn enabled_for_or<T: Actor + Group>(flag: &str, actor: &T, default: bool) -> bool {
+    default_client()
+        .await
+        .enabled_for_or(flag, actor, default)
+        .await
+}
+
+pub async fn try_enabled_for<T: Actor + Group>(flag: &str, actor: &T) -> Result<bool, Error> {
+    default_client().await.try_enabled_for(flag, actor).await
+}
+
+pub async fn evaluate_for<T: Actor + Group>(flag: &str, actor: &T) -> Result<Evaluation, Error> {
+    default_client().await.evaluate_for(flag, actor).await
+}
+
+pub async fn disabled_for<T: Actor + Group>(flag: &str, actor: &T) -> bool {
+    default_client().await.disabled_for(flag, actor).await
+}
+
+pub async fn enable_percentage_of_time(flag: &str, percentage: f64) -> Output {
+    default_client()
+        .await
+        .enable_percentage_of_time(flag, percentage)
+        .await
+}
+
+pub async fn disable_percentage_of_time(flag: &str) -> Output {
+    default_client()
+        .await
+        .disable_percentage_of_time(flag)
+        .await
+}
+
+pub async fn enable_percentage_of_actors(flag: &str, percentage: f64) -> Output {
+    default_client()
+        .await
+        .enable_percentage_of_actors(flag, percentage)
+        .await
+}
+
+pub async fn disable_percentage_of_actors(flag: &str) -> Output {
+    default_client()
+        .await
+        .disable_percentage_of_actors(flag)
+        .await
+}
+
+pub async fn enable_for_group(flag: &str, group_name: &str) -> Output {
+    default_client()
+        .await
+        .enable_for_group(flag, group_name)
+        .await
+}
+
+pub async fn disable_for_group(flag: &str, group_name: &str) -> Output {
+    default_client()
+        .await
+        .disable_for_group(flag, group_name)
+        .await
+}
+
+pub async fn clear(flag: &str) -> Result<(), Error> {
+    default_client().await.clear(flag).await
+}
+
+pub async fn clear_for<T: Actor>(flag: &str, actor: &T) -> Result<(), Error> {
+    default_client().await.clear_for(flag, actor).await
+}
+
+pub async fn clear_for_group(flag: &str, group_name: &str) -> Result<(), Error> {
+    default_client()
+        .await
+        .clear_for_group(flag, group_name)
+        .await
+}
+
+pub async fn clear_boolean(flag: &str) -> Result<(), Error> {
+    default_client().await.clear_boolean(flag).await
+}
+
+pub async fn clear_percentage(flag: &str) -> Result<(), Error> {
+    default_client().await.clear_percentage(flag).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncFunWithFlags;
+    use crate::backend::{AsyncStorageBackend, FlagOutput, SetOutput};
+    use crate::config::BackendType;
+    use crate::{Error, FeatureFlag, Flag};
+
+    use std::collections::HashSet;
+
+    struct AlwaysEnabled;
+
+    #[async_trait::async_trait]
+    impl AsyncStorageBackend for AlwaysEnabled {
+        async fn get_flag(&self, flag_name: &str) -> FlagOutput {
+            Ok(Flag::new(
+                flag_name,
+                vec![FeatureFlag::Boolean {
+                    name: flag_name.to_string(),
+                    enabled: true,
+                }],
+            ))
+        }
+
+        async fn set(&self, flag: FeatureFlag) -> SetOutput {
+            Ok(flag)
+        }
+
+        async fn clear(&self, _flag_name: &str) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn clear_gate(&self, _gate: FeatureFlag) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn list(&self) -> Result<HashSet<String>, Error> {
+            Ok(HashSet::new())
+        }
+
+        fn backend_name(&self) -> &'static str {
+            "always-enabled"
+        }
+    }
+
+    #[tokio::test]
+    async fn async_client_enabled_test() {
+        let client = AsyncFunWithFlags::with_backend(AlwaysEnabled);
+
+        assert!(client.enable("oke").await.is_ok());
+        assert!(client.enabled("oke").await);
+    }
+
+    #[tokio::test]
+    async fn async_builder_with_backend_type_test() {
+        let client = AsyncFunWithFlags::builder()
+            .url("")
+            .backend_type(BackendType::Null)
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!("null", client.backend().backend_name());
+    }
+}
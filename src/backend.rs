@@ -1,95 +1,597 @@
-use crate::FeatureFlag;
-// #[cfg(feature = "r2d2")]
-// use crate::Error;
-
-// #[derive(Debug, PartialEq)]
-// pub struct FakeConnection(bool);
-
-// pub struct FakeManager;
-
-// #[cfg(feature = "r2d2")]
-// impl r2d2::ManageConnection for FakeManager {
-//     type Connection = FakeConnection;
-//     type Error = Error;
-
-//     fn connect(&self) -> Result<FakeConnection, Error> {
-//         Ok(FakeConnection(true))
-//     }
-
-//     fn is_valid(&self, _: &mut FakeConnection) -> Result<(), Error> {
-//         Ok(())
-//     }
-
-//     fn has_broken(&self, _: &mut FakeConnection) -> bool {
-//         false
-//     }
-// }
-
-cfg_if::cfg_if! {
-    if #[cfg(test)] {
-        pub mod null;
-        pub use null::{MockBackend as Backend, DBConnection, GetOutput, SetOutput, DB};
-    } else if #[cfg(all(feature = "redis-backend", feature = "cached"))]{
-        pub mod redis;
-        pub mod cached;
-        pub use self::cached::Backend;
-        pub use self::redis::{Backend as DataBackend, DBConnection, GetOutput, SetOutput, DB, ConnectionResult};
-    } else if #[cfg(all(feature = "postgres-backend", feature = "cached"))]{
-        pub mod postgres;
-        pub mod cached;
-        pub use self::cached::Backend;
-        pub use self::postgres::{Backend as DataBackend, DBConnection, GetOutput, SetOutput, DB, ConnectionResult};
-    } else if #[cfg(feature = "redis-backend")]{
-        pub mod redis;
-        pub use self::redis::{Backend, DBConnection, GetOutput, SetOutput, DB};
-    } else if #[cfg(feature = "postgres-backend")] {
-        pub mod postgres;
-        pub use self::postgres::{Backend, DBConnection, GetOutput, SetOutput, DB};
-    } else {
-        pub mod null;
-        pub use null::{MockBackend as Backend, DBConnection, GetOutput, SetOutput, DB};
-    }
-}
-
-pub fn set(conn: &DBConnection, flag: FeatureFlag) -> SetOutput {
-    Backend::set(conn, flag)
-}
-
-pub fn get(conn: &DBConnection, flag: FeatureFlag) -> GetOutput {
-    Backend::get(conn, flag)
-}
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     #[test]
-//     fn connection_name() {
-//         assert_eq!("mock", Backend::backend_name());
-//     }
-
-//     #[test]
-//     fn get_returns_error() {
-//         use crate::establish_connection;
-
-//         let conn = establish_connection();
-//         let ff = FeatureFlag::Boolean {
-//             name: "something".to_string(),
-//             enabled: true,
-//         };
-//         assert!(Backend::get(&conn, ff).is_err());
-//     }
-
-//     #[test]
-//     fn set_returns_error() {
-//         use crate::establish_connection;
-
-//         let conn = establish_connection();
-//         let ff = FeatureFlag::Boolean {
-//             name: "something".to_string(),
-//             enabled: true,
-//         };
-//         assert!(Backend::set(&conn, ff).is_err());
-//     }
-
-// }
+#[cfg(any(feature = "redis-backend", feature = "postgres-backend"))]
+use crate::config::PoolConfig;
+#[cfg(feature = "postgres-backend")]
+use crate::config::PostgresConfig;
+#[cfg(feature = "redis-backend")]
+use crate::config::RedisConfig;
+use crate::config::{BackendType, CacheConfig, ConfigError, RawConfig, WritePolicy};
+use crate::{Error, FeatureFlag, Flag};
+
+use mockall::automock;
+use std::collections::HashSet;
+
+/// implements `AsyncStorageBackend` for a sync backend that is cheap to clone,
+/// every call runs on the blocking threads of tokio so the blocking client does not stall the executor
+#[cfg(feature = "async")]
+#[cfg_attr(
+    not(any(
+        feature = "sqlite-backend",
+        feature = "mysql-backend",
+        feature = "file-backend"
+    )),
+    allow(unused_macros)
+)]
+macro_rules! blocking_async_backend {
+    ($backend:ty) => {
+        #[async_trait::async_trait]
+        impl crate::backend::AsyncStorageBackend for $backend {
+            async fn set(&self, flag: crate::FeatureFlag) -> crate::backend::SetOutput {
+                crate::backend::blocking(self, move |backend| {
+                    crate::backend::StorageBackend::set(backend, flag)
+                })
+                .await
+            }
+
+            async fn get_flag(&self, flag_name: &str) -> crate::backend::FlagOutput {
+                let flag_name = flag_name.to_string();
+                crate::backend::blocking(self, move |backend| {
+                    crate::backend::StorageBackend::get_flag(backend, &flag_name)
+                })
+                .await
+            }
+
+            async fn clear(&self, flag_name: &str) -> Result<(), crate::Error> {
+                let flag_name = flag_name.to_string();
+                crate::backend::blocking(self, move |backend| {
+                    crate::backend::StorageBackend::clear(backend, &flag_name)
+                })
+                .await
+            }
+
+            async fn clear_gate(&self, gate: crate::FeatureFlag) -> Result<(), crate::Error> {
+                crate::backend::blocking(self, move |backend| {
+                    crate::backend::StorageBackend::clear_gate(backend, gate)
+                })
+                .await
+            }
+
+            async fn list(&self) -> Result<std::collections::HashSet<String>, crate::Error> {
+                crate::backend::blocking(self, crate::backend::StorageBackend::list).await
+            }
+
+            async fn all_flags(&self) -> Result<Vec<crate::Flag>, crate::Error> {
+                crate::backend::blocking(self, crate::backend::StorageBackend::all_flags).await
+            }
+
+            fn backend_name(&self) -> &'static str {
+                crate::backend::StorageBackend::backend_name(self)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "cached")]
+pub mod cached;
+pub mod composite;
+#[cfg(feature = "file-backend")]
+pub mod file;
+pub mod memory;
+#[cfg(feature = "mysql-backend")]
+pub mod mysql;
+pub mod null;
+#[cfg(feature = "postgres-backend")]
+pub mod postgres;
+#[cfg(all(feature = "async", feature = "postgres-backend"))]
+pub mod postgres_async;
+#[cfg(feature = "redis-backend")]
+pub mod redis;
+#[cfg(all(feature = "async", feature = "redis-backend"))]
+pub mod redis_async;
+#[cfg(any(
+    feature = "postgres-backend",
+    feature = "sqlite-backend",
+    feature = "mysql-backend"
+))]
+mod sql;
+#[cfg(feature = "sqlite-backend")]
+pub mod sqlite;
+
+pub type SetOutput = Result<FeatureFlag, Error>;
+pub type GetOutput = Result<FeatureFlag, Error>;
+pub type FlagOutput = Result<Flag, Error>;
+
+/// called with the name of a flag that another process changed, return `false` to stop listening
+///
+/// Called with `None` after (re)connecting, changes could have been missed so every flag should be evicted.
+pub type ChangeCallback = Box<dyn Fn(Option<&str>) -> bool + Send + 'static>;
+
+/// channel the changes are sent on, the same as the elixir library uses
+#[cfg(any(feature = "redis-backend", feature = "postgres-backend"))]
+pub(crate) const CHANGES_CHANNEL: &str = "fun_with_flags_changes";
+
+/// Storage for the feature flags.
+///
+/// Implemented by the Redis, Postgres, SQLite, file, in memory, cached and null backends,
+/// implement it yourself to plug in your own storage.
+#[automock]
+pub trait StorageBackend: Send + Sync {
+    /// gets the flag with all its stored gates in one round trip, the flag has no gates if it is not stored
+    fn get_flag(&self, flag_name: &str) -> FlagOutput;
+
+    /// gets the stored gate that is the `same` as `flag`, returns `FeatureFlag::Empty` if it is not stored
+    fn get(&self, flag: FeatureFlag) -> GetOutput {
+        Ok(self.get_flag(flag.name())?.find(&flag))
+    }
+
+    /// stores the gate, returns the gate as it is stored
+    fn set(&self, flag: FeatureFlag) -> SetOutput;
+
+    /// removes all the gates of the flag
+    fn clear(&self, flag_name: &str) -> Result<(), Error>;
+
+    /// removes the stored gate that is the `same` as `gate`, for percentage gates both kinds are removed
+    fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error>;
+
+    /// names of all the stored flags
+    fn list(&self) -> Result<HashSet<String>, Error>;
+
+    fn backend_name(&self) -> &'static str;
+
+    /// tells other processes that the flag changed, so they can evict it from their cache
+    fn publish_change(&self, _flag_name: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// calls `on_change` from a background thread whenever another process changed a flag,
+    /// does nothing for backends that can not notify other processes
+    fn subscribe_changes(&self, _on_change: ChangeCallback) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// replaces the settings of the cache, fails for backends without a cache
+    fn configure_cache(&self, _config: CacheConfig) -> Result<(), Error> {
+        Err(Error::Custom(format!(
+            "{} backend has no cache, enable the cached feature",
+            self.backend_name()
+        )))
+    }
+
+    /// all the stored flags with their gates, sorted by name
+    fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        let mut flag_names: Vec<String> = self.list()?.into_iter().collect();
+        flag_names.sort();
+
+        let mut flags = Vec::with_capacity(flag_names.len());
+        for flag_name in flag_names {
+            flags.push(self.get_flag(&flag_name)?);
+        }
+
+        Ok(flags)
+    }
+
+    /// removes all the stored flags
+    fn clear_all(&self) -> Result<(), Error> {
+        for flag_name in self.list()? {
+            self.clear(&flag_name)?
+        }
+
+        Ok(())
+    }
+}
+
+/// Async version of `StorageBackend`, uses the same storage format so async and sync processes can share the data.
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+pub trait AsyncStorageBackend: Send + Sync {
+    /// gets the flag with all its stored gates in one round trip, the flag has no gates if it is not stored
+    async fn get_flag(&self, flag_name: &str) -> FlagOutput;
+
+    /// gets the stored gate that is the `same` as `flag`, returns `FeatureFlag::Empty` if it is not stored
+    async fn get(&self, flag: FeatureFlag) -> GetOutput {
+        Ok(self.get_flag(flag.name()).await?.find(&flag))
+    }
+
+    /// stores the gate, returns the gate as it is stored
+    async fn set(&self, flag: FeatureFlag) -> SetOutput;
+
+    /// removes all the gates of the flag
+    async fn clear(&self, flag_name: &str) -> Result<(), Error>;
+
+    /// removes the stored gate that is the `same` as `gate`, for percentage gates both kinds are removed
+    async fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error>;
+
+    /// names of all the stored flags
+    async fn list(&self) -> Result<HashSet<String>, Error>;
+
+    fn backend_name(&self) -> &'static str;
+
+    /// tells other processes that the flag changed, so they can evict it from their cache
+    async fn publish_change(&self, _flag_name: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// calls `on_change` from a background thread whenever another process changed a flag,
+    /// does nothing for backends that can not notify other processes
+    fn subscribe_changes(&self, _on_change: ChangeCallback) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// replaces the settings of the cache, fails for backends without a cache
+    fn configure_cache(&self, _config: CacheConfig) -> Result<(), Error> {
+        Err(Error::Custom(format!(
+            "{} backend has no cache, enable the cached feature",
+            self.backend_name()
+        )))
+    }
+
+    /// all the stored flags with their gates, sorted by name
+    async fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        let mut flag_names: Vec<String> = self.list().await?.into_iter().collect();
+        flag_names.sort();
+
+        let mut flags = Vec::with_capacity(flag_names.len());
+        for flag_name in flag_names {
+            flags.push(self.get_flag(&flag_name).await?);
+        }
+
+        Ok(flags)
+    }
+
+    /// removes all the stored flags
+    async fn clear_all(&self) -> Result<(), Error> {
+        for flag_name in self.list().await? {
+            self.clear(&flag_name).await?
+        }
+
+        Ok(())
+    }
+}
+
+/// runs `call` with a clone of the sync backend on the blocking threads of tokio, see `blocking_async_backend`
+#[cfg(feature = "async")]
+#[cfg_attr(
+    not(any(
+        feature = "sqlite-backend",
+        feature = "mysql-backend",
+        feature = "file-backend"
+    )),
+    allow(dead_code)
+)]
+pub(crate) async fn blocking<B, T, F>(backend: &B, call: F) -> Result<T, Error>
+where
+    B: StorageBackend + Clone + 'static,
+    T: Send + 'static,
+    F: FnOnce(&B) -> Result<T, Error> + Send + 'static,
+{
+    let backend = backend.clone();
+    tokio::task::spawn_blocking(move || call(&backend))
+        .await
+        .map_err(|e| Error::Backend(Box::new(e)))?
+}
+
+/// creates the backend picked by `backend_type`, `BackendType::Auto` picks the backend based on the scheme of the url
+pub fn establish(backend_type: BackendType, url: &str) -> Result<Box<dyn StorageBackend>, Error> {
+    establish_with_cache(backend_type, url, CacheConfig::default())
+}
+
+/// same as `establish`, the cache uses `cache_config` when the cached feature is enabled
+pub fn establish_with_cache(
+    backend_type: BackendType,
+    url: &str,
+    cache_config: CacheConfig,
+) -> Result<Box<dyn StorageBackend>, Error> {
+    with_cache(
+        establish_data_backend(backend_type, url, None)?,
+        cache_config,
+    )
+}
+
+/// creates the backend that is configured in `config`
+pub fn from_config(config: &RawConfig) -> Result<Box<dyn StorageBackend>, Error> {
+    let backend: Box<dyn StorageBackend> = match config.what_type() {
+        Some(BackendType::Composite) => {
+            let (members, write) = composite_parts(config)?;
+            let primary = composite_primary(config)?;
+            let backends = members
+                .iter()
+                .map(|(backend_type, url)| establish_data_backend(*backend_type, url, Some(config)))
+                .collect::<Result<Vec<_>, Error>>()?;
+            Box::new(composite::Backend::new(backends, write)?.with_primary(primary)?)
+        }
+        _ => {
+            let (backend_type, url) = config_parts(config)?;
+            establish_data_backend(backend_type, &url, Some(config))?
+        }
+    };
+
+    with_cache(backend, config.cache())
+}
+
+fn with_cache(
+    backend: Box<dyn StorageBackend>,
+    cache_config: CacheConfig,
+) -> Result<Box<dyn StorageBackend>, Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "cached")] {
+            Ok(Box::new(cached::Backend::with_config(backend, cache_config)?))
+        } else {
+            let _ = cache_config;
+            Ok(backend)
+        }
+    }
+}
+
+/// async version of `establish`
+#[cfg(feature = "async")]
+pub async fn establish_async(
+    backend_type: BackendType,
+    url: &str,
+) -> Result<Box<dyn AsyncStorageBackend>, Error> {
+    establish_async_with_cache(backend_type, url, CacheConfig::default()).await
+}
+
+/// async version of `establish_with_cache`
+#[cfg(feature = "async")]
+pub async fn establish_async_with_cache(
+    backend_type: BackendType,
+    url: &str,
+    cache_config: CacheConfig,
+) -> Result<Box<dyn AsyncStorageBackend>, Error> {
+    with_async_cache(
+        establish_async_data_backend(backend_type, url, None).await?,
+        cache_config,
+    )
+}
+
+/// async version of `from_config`
+#[cfg(feature = "async")]
+pub async fn from_config_async(config: &RawConfig) -> Result<Box<dyn AsyncStorageBackend>, Error> {
+    let backend: Box<dyn AsyncStorageBackend> = match config.what_type() {
+        Some(BackendType::Composite) => {
+            let (members, write) = composite_parts(config)?;
+            let primary = composite_primary(config)?;
+            let mut backends = Vec::with_capacity(members.len());
+            for (backend_type, url) in members {
+                backends
+                    .push(establish_async_data_backend(backend_type, &url, Some(config)).await?);
+            }
+            Box::new(composite::AsyncBackend::new(backends, write)?.with_primary(primary)?)
+        }
+        _ => {
+            let (backend_type, url) = config_parts(config)?;
+            establish_async_data_backend(backend_type, &url, Some(config)).await?
+        }
+    };
+
+    with_async_cache(backend, config.cache())
+}
+
+#[cfg(feature = "async")]
+#[cfg_attr(
+    not(any(feature = "redis-backend", feature = "postgres-backend")),
+    allow(unused_variables)
+)]
+async fn establish_async_data_backend(
+    backend_type: BackendType,
+    url: &str,
+    config: Option<&RawConfig>,
+) -> Result<Box<dyn AsyncStorageBackend>, Error> {
+    let backend_type = match backend_type {
+        BackendType::Auto => BackendType::from_url(url)?,
+        other => other,
+    };
+
+    let backend: Box<dyn AsyncStorageBackend> = match backend_type {
+        #[cfg(feature = "redis-backend")]
+        BackendType::Redis => {
+            Box::new(redis_async::Backend::with_config(&redis_config(config, url)).await?)
+        }
+        #[cfg(feature = "postgres-backend")]
+        BackendType::Postgres => Box::new(postgres_async::Backend::with_config(&postgres_config(
+            config, url,
+        ))?),
+        #[cfg(feature = "sqlite-backend")]
+        BackendType::Sqlite => Box::new(sqlite::Backend::establish(url)?),
+        #[cfg(feature = "mysql-backend")]
+        BackendType::Mysql => Box::new(mysql::Backend::establish(url)?),
+        #[cfg(feature = "file-backend")]
+        BackendType::File => Box::new(file::Backend::from_url(url)?),
+        BackendType::Memory => Box::new(memory::Backend::new()),
+        BackendType::Null => Box::new(null::Backend),
+        BackendType::Composite => return Err(composite_without_config()),
+        #[allow(unreachable_patterns)]
+        other => return Err(not_enabled(other)),
+    };
+
+    Ok(backend)
+}
+
+#[cfg(feature = "async")]
+fn with_async_cache(
+    backend: Box<dyn AsyncStorageBackend>,
+    cache_config: CacheConfig,
+) -> Result<Box<dyn AsyncStorageBackend>, Error> {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "cached")] {
+            Ok(Box::new(cached::AsyncBackend::with_config(backend, cache_config)?))
+        } else {
+            let _ = cache_config;
+            Ok(backend)
+        }
+    }
+}
+
+/// runs `listen` on a background thread until it returns `Ok`, which it does when `on_change` asks to stop,
+/// when it fails because of a lost connection it is restarted after a second
+#[cfg(any(feature = "redis-backend", feature = "postgres-backend"))]
+pub(crate) fn spawn_listener<F>(on_change: ChangeCallback, listen: F) -> Result<(), Error>
+where
+    F: Fn(&ChangeCallback) -> Result<(), Error> + Send + 'static,
+{
+    std::thread::Builder::new()
+        .name(String::from(CHANGES_CHANNEL))
+        .spawn(move || loop {
+            match listen(&on_change) {
+                Ok(()) => return,
+                Err(_) => std::thread::sleep(std::time::Duration::from_secs(1)),
+            }
+        })
+        .map_err(|e| Error::Backend(Box::new(e)))?;
+
+    Ok(())
+}
+
+fn config_parts(config: &RawConfig) -> Result<(BackendType, String), ConfigError> {
+    let backend_type = config
+        .what_type()
+        .ok_or_else(|| ConfigError::Message(String::from("backend type not found")))?;
+    let url = config.to_url().ok_or_else(|| {
+        ConfigError::Message(String::from("database config can not find correct url"))
+    })?;
+
+    Ok((backend_type, url))
+}
+
+/// the type and url of every backend in `[composite] backends`, with the write policy
+fn composite_parts(config: &RawConfig) -> Result<(Vec<(BackendType, String)>, WritePolicy), Error> {
+    let composite = config
+        .composite_config
+        .as_ref()
+        .ok_or_else(composite_without_config)?;
+
+    let members = composite
+        .backends
+        .iter()
+        .map(|backend_type| {
+            let url = config.url_for(*backend_type).ok_or_else(|| {
+                ConfigError::Message(format!(
+                    "composite backend can not find the url of {:?}",
+                    backend_type
+                ))
+            })?;
+            Ok((*backend_type, url))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok((members, composite.write))
+}
+
+/// the index of `[composite] primary` in the backends, the first backend when it is not set
+fn composite_primary(config: &RawConfig) -> Result<usize, Error> {
+    let composite = config
+        .composite_config
+        .as_ref()
+        .ok_or_else(composite_without_config)?;
+
+    match composite.primary {
+        Some(primary) => composite
+            .backends
+            .iter()
+            .position(|backend_type| *backend_type == primary)
+            .ok_or_else(|| {
+                Error::from(ConfigError::Message(format!(
+                    "composite primary {:?} is not one of its backends",
+                    primary
+                )))
+            }),
+        None => Ok(0),
+    }
+}
+
+fn composite_without_config() -> Error {
+    Error::from(ConfigError::Message(String::from(
+        "composite backend needs a [composite] section with its backends",
+    )))
+}
+
+fn not_enabled(backend_type: BackendType) -> Error {
+    Error::from(ConfigError::Message(format!(
+        "backend {:?} is not enabled, enable it with the cargo feature",
+        backend_type
+    )))
+}
+
+/// `config` has the sections with the settings of the backends, `None` when only the url is known
+#[cfg_attr(
+    not(any(feature = "redis-backend", feature = "postgres-backend")),
+    allow(clippy::only_used_in_recursion)
+)]
+fn establish_data_backend(
+    backend_type: BackendType,
+    url: &str,
+    config: Option<&RawConfig>,
+) -> Result<Box<dyn StorageBackend>, Error> {
+    match backend_type {
+        BackendType::Auto => establish_data_backend(BackendType::from_url(url)?, url, config),
+        #[cfg(feature = "redis-backend")]
+        BackendType::Redis => Ok(Box::new(redis::Backend::with_config(&redis_config(
+            config, url,
+        ))?)),
+        #[cfg(feature = "postgres-backend")]
+        BackendType::Postgres => Ok(Box::new(postgres::Backend::with_config(&postgres_config(
+            config, url,
+        ))?)),
+        #[cfg(feature = "sqlite-backend")]
+        BackendType::Sqlite => Ok(Box::new(sqlite::Backend::establish(url)?)),
+        #[cfg(feature = "mysql-backend")]
+        BackendType::Mysql => Ok(Box::new(mysql::Backend::establish(url)?)),
+        #[cfg(feature = "file-backend")]
+        BackendType::File => Ok(Box::new(file::Backend::from_url(url)?)),
+        BackendType::Memory => Ok(Box::new(memory::Backend::new())),
+        BackendType::Null => Ok(Box::new(null::Backend)),
+        BackendType::Composite => Err(composite_without_config()),
+        #[allow(unreachable_patterns)]
+        other => Err(not_enabled(other)),
+    }
+}
+
+/// the `[postgres]` section with `url`, which can also come from the `DATABASE_*` variables
+#[cfg(feature = "postgres-backend")]
+fn postgres_config(config: Option<&RawConfig>, url: &str) -> PostgresConfig {
+    PostgresConfig {
+        url: url.to_string(),
+        ..config.map(RawConfig::postgres).unwrap_or_default()
+    }
+}
+
+/// the `[redis]` section with `url`, which can also come from the `DATABASE_*` variables
+#[cfg(feature = "redis-backend")]
+fn redis_config(config: Option<&RawConfig>, url: &str) -> RedisConfig {
+    RedisConfig {
+        url: url.to_string(),
+        ..config.map(RawConfig::redis).unwrap_or_default()
+    }
+}
+
+/// the r2d2 pool with the settings of the `[redis]` or `[postgres]` section,
+/// `max_size` is the size of the pool when it is not set
+#[cfg(any(feature = "redis-backend", feature = "postgres-backend"))]
+pub(crate) fn pool_builder<M: r2d2::ManageConnection>(
+    pool: &PoolConfig,
+    max_size: u32,
+) -> Result<r2d2::Builder<M>, Error> {
+    pool.validate()?;
+
+    let max_size = pool.max_size.unwrap_or(max_size);
+    if pool.min_idle.is_some_and(|min_idle| min_idle > max_size) {
+        return Err(Error::Config(ConfigError::Message(format!(
+            "pool min_idle can not be larger than max_size {}",
+            max_size
+        ))));
+    }
+
+    let mut builder = r2d2::Pool::builder()
+        .max_size(max_size)
+        .min_idle(pool.min_idle);
+    if let Some(connection_timeout) = pool.connection_timeout() {
+        builder = builder.connection_timeout(connection_timeout);
+    }
+    if let Some(idle_timeout) = pool.idle_timeout() {
+        builder = builder.idle_timeout(Some(idle_timeout));
+    }
+    if let Some(max_lifetime) = pool.max_lifetime() {
+        builder = builder.max_lifetime(Some(max_lifetime));
+    }
+
+    Ok(builder)
+}
@@ -1,114 +1,405 @@
-use std::sync::Mutex;
-use std::time::Duration;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
 
-#[allow(unused_imports)]
-use crate::Error;
-
-use crate::FeatureFlag;
+#[cfg(feature = "async")]
+use crate::backend::AsyncStorageBackend;
+use crate::backend::{ChangeCallback, FlagOutput, SetOutput, StorageBackend};
+use crate::config::CacheConfig;
+use crate::{Error, FeatureFlag, Flag};
 use lru_time_cache::LruCache;
-use state::Storage;
 
-use crate::backend::{ConnectionResult, DBConnection, DataBackend, GetOutput, SetOutput};
+/// wraps another backend and caches the flags it returns
+///
+/// Changes are published to other processes and changes of other processes evict the flag from the cache,
+/// if the wrapped backend supports that (Redis does with the same channel as the elixir library, Postgres with `LISTEN`).
+pub struct Backend {
+    backend: Box<dyn StorageBackend>,
+    cache: Arc<Cache>,
+}
+
+impl Backend {
+    pub fn new(backend: Box<dyn StorageBackend>) -> Result<Backend, Error> {
+        Self::with_config(backend, CacheConfig::default())
+    }
+
+    pub fn with_config(
+        backend: Box<dyn StorageBackend>,
+        config: CacheConfig,
+    ) -> Result<Backend, Error> {
+        config.validate()?;
+        let cache = Arc::new(Cache::new(config));
+        backend.subscribe_changes(Cache::evict_on_change(&cache))?;
+
+        Ok(Backend { backend, cache })
+    }
+
+    pub fn flush_cache(&self) {
+        self.cache.flush()
+    }
 
-lazy_static::lazy_static! {
-    static ref GLOBAL_MAP: Storage<Mutex<LruCache<String, FeatureFlag>>> = {
-        let storage = Storage::new();
-        let initial_map = LruCache::with_expiry_duration_and_capacity(Duration::from_secs(60), 1000);
-        storage.set(Mutex::new(initial_map));
-        storage
-    };
+    /// publishes the change if the write succeeded, a failed publish is not an error
+    /// because the write itself went through and other processes still expire the flag after the ttl
+    fn published<T>(&self, flag_name: &str, output: Result<T, Error>) -> Result<T, Error> {
+        if output.is_ok() {
+            let _ = self.backend.publish_change(flag_name);
+        }
+        output
+    }
 }
 
-pub struct Backend;
+/// async version of the cached `Backend`
+#[cfg(feature = "async")]
+pub struct AsyncBackend {
+    backend: Box<dyn AsyncStorageBackend>,
+    cache: Arc<Cache>,
+}
 
-impl Backend {
-    pub fn get(conn: &DBConnection, flag: FeatureFlag) -> GetOutput {
-        if let Some(feature_flag) = get_from_cache(&flag) {
-            Ok(feature_flag)
-        } else {
-            DataBackend::get(conn, flag)
+#[cfg(feature = "async")]
+impl AsyncBackend {
+    pub fn new(backend: Box<dyn AsyncStorageBackend>) -> Result<AsyncBackend, Error> {
+        Self::with_config(backend, CacheConfig::default())
+    }
+
+    pub fn with_config(
+        backend: Box<dyn AsyncStorageBackend>,
+        config: CacheConfig,
+    ) -> Result<AsyncBackend, Error> {
+        config.validate()?;
+        let cache = Arc::new(Cache::new(config));
+        backend.subscribe_changes(Cache::evict_on_change(&cache))?;
+
+        Ok(AsyncBackend { backend, cache })
+    }
+
+    pub fn flush_cache(&self) {
+        self.cache.flush()
+    }
+
+    /// async version of `Backend::published`
+    async fn published<T>(&self, flag_name: &str, output: Result<T, Error>) -> Result<T, Error> {
+        if output.is_ok() {
+            let _ = self.backend.publish_change(flag_name).await;
         }
+        output
     }
+}
 
-    pub fn set(conn: &DBConnection, flag: FeatureFlag) -> SetOutput {
-        match DataBackend::set(conn, flag) {
-            Ok(flag) => {
-                set_in_cache(flag.clone());
-                Ok(flag)
-            }
-            Err(e) => Err(e),
+/// flags by name, with the time they were cached
+struct Cache {
+    inner: Mutex<CacheInner>,
+}
+
+struct CacheInner {
+    flags: LruCache<String, (Flag, Instant)>,
+    config: CacheConfig,
+}
+
+impl Cache {
+    fn new(config: CacheConfig) -> Cache {
+        Cache {
+            inner: Mutex::new(CacheInner {
+                flags: LruCache::with_capacity(config.capacity()),
+                config,
+            }),
         }
     }
 
-    pub fn create_conn(pool: &DBConnection) -> ConnectionResult {
-        DataBackend::create_conn(pool)
+    /// the flag if it is cached shorter than its ttl
+    fn get(&self, flag_name: &str) -> Option<Flag> {
+        let mut inner = self.inner.lock().unwrap();
+        let ttl = inner.config.ttl_for(flag_name);
+
+        let (flag, cached_at) = inner.flags.get(flag_name)?;
+        if cached_at.elapsed() < ttl {
+            return Some(flag.clone());
+        }
+
+        inner.flags.remove(flag_name);
+        None
     }
-}
 
-#[cfg(feature = "redis-backend")]
-impl Backend {
-    pub fn all_flags_names(
-        pool: &DBConnection,
-    ) -> Result<std::collections::HashSet<String>, Error> {
-        DataBackend::all_flags_names(pool)
+    fn set(&self, flag: Flag) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if flag.is_empty() && !inner.config.cache_empty() {
+            return;
+        }
+
+        if inner.config.ttl_for(&flag.name).is_zero() {
+            return;
+        }
+
+        inner
+            .flags
+            .insert(flag.name.clone(), (flag, Instant::now()));
     }
 
-    pub fn clean_all(pool: &DBConnection) -> Result<(), Error> {
-        flush_cache();
-        DataBackend::clean_all(pool)
+    fn remove(&self, flag_name: &str) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.flags.remove(flag_name);
     }
 
-    pub fn clean(pool: &DBConnection, flag_name: &str) -> Result<(), Error> {
-        DataBackend::clean(pool, flag_name)
+    fn flush(&self) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.flags.clear()
     }
-}
 
-fn get_from_cache(flag: &FeatureFlag) -> Option<FeatureFlag> {
-    let key = flag.to_cache_key();
+    /// flushes the cache, so every flag is cached with the new settings
+    fn configure(&self, config: CacheConfig) {
+        let mut inner = self.inner.lock().unwrap();
 
-    let mut cache = GLOBAL_MAP.get().lock().unwrap();
+        inner.flags = LruCache::with_capacity(config.capacity());
+        inner.config = config;
+    }
+
+    /// evicts the changed flag, or every flag when changes could have been missed,
+    /// stops listening once the cache is dropped
+    fn evict_on_change(cache: &Arc<Cache>) -> ChangeCallback {
+        let cache: Weak<Cache> = Arc::downgrade(cache);
 
-    cache.get(&key).cloned()
+        Box::new(move |flag_name| match (cache.upgrade(), flag_name) {
+            (Some(cache), Some(flag_name)) => {
+                cache.remove(flag_name);
+                true
+            }
+            (Some(cache), None) => {
+                cache.flush();
+                true
+            }
+            (None, _) => false,
+        })
+    }
 }
 
-fn set_in_cache(flag: FeatureFlag) {
-    let key = flag.to_cache_key();
+impl StorageBackend for Backend {
+    fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        if let Some(flag) = self.cache.get(flag_name) {
+            return Ok(flag);
+        }
 
-    let mut cache = GLOBAL_MAP.get().lock().unwrap();
+        let flag = self.backend.get_flag(flag_name)?;
+        self.cache.set(flag.clone());
+        Ok(flag)
+    }
 
-    cache.insert(key, flag);
-}
+    fn set(&self, flag: FeatureFlag) -> SetOutput {
+        let flag_name = flag.name().to_string();
+        let output = self.backend.set(flag);
+        self.cache.remove(&flag_name);
+        self.published(&flag_name, output)
+    }
+
+    fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        let output = self.backend.clear(flag_name);
+        self.cache.remove(flag_name);
+        self.published(flag_name, output)
+    }
 
-pub fn flush_cache() {
-    let mut cache = GLOBAL_MAP.get().lock().unwrap();
+    fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        let flag_name = gate.name().to_string();
+        let output = self.backend.clear_gate(gate);
+        self.cache.remove(&flag_name);
+        self.published(&flag_name, output)
+    }
 
-    cache.clear()
+    fn list(&self) -> Result<HashSet<String>, Error> {
+        self.backend.list()
+    }
+
+    fn backend_name(&self) -> &'static str {
+        self.backend.backend_name()
+    }
+
+    fn publish_change(&self, flag_name: &str) -> Result<(), Error> {
+        self.backend.publish_change(flag_name)
+    }
+
+    fn subscribe_changes(&self, on_change: ChangeCallback) -> Result<(), Error> {
+        self.backend.subscribe_changes(on_change)
+    }
+
+    fn configure_cache(&self, config: CacheConfig) -> Result<(), Error> {
+        config.validate()?;
+        self.cache.configure(config);
+        Ok(())
+    }
 }
 
-#[test]
-fn oke() {
-    let ff = FeatureFlag::Boolean {
-        name: "oke".to_string(),
-        enabled: true,
-    };
-    set_in_cache(ff.clone());
-    let f = get_from_cache(&ff);
-    println!("{:?}", f);
-
-    panic!();
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl AsyncStorageBackend for AsyncBackend {
+    async fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        if let Some(flag) = self.cache.get(flag_name) {
+            return Ok(flag);
+        }
+
+        let flag = self.backend.get_flag(flag_name).await?;
+        self.cache.set(flag.clone());
+        Ok(flag)
+    }
+
+    async fn set(&self, flag: FeatureFlag) -> SetOutput {
+        let flag_name = flag.name().to_string();
+        let output = self.backend.set(flag).await;
+        self.cache.remove(&flag_name);
+        self.published(&flag_name, output).await
+    }
+
+    async fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        let output = self.backend.clear(flag_name).await;
+        self.cache.remove(flag_name);
+        self.published(flag_name, output).await
+    }
+
+    async fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        let flag_name = gate.name().to_string();
+        let output = self.backend.clear_gate(gate).await;
+        self.cache.remove(&flag_name);
+        self.published(&flag_name, output).await
+    }
+
+    async fn list(&self) -> Result<HashSet<String>, Error> {
+        self.backend.list().await
+    }
+
+    fn backend_name(&self) -> &'static str {
+        self.backend.backend_name()
+    }
+
+    async fn publish_change(&self, flag_name: &str) -> Result<(), Error> {
+        self.backend.publish_change(flag_name).await
+    }
+
+    fn subscribe_changes(&self, on_change: ChangeCallback) -> Result<(), Error> {
+        self.backend.subscribe_changes(on_change)
+    }
+
+    fn configure_cache(&self, config: CacheConfig) -> Result<(), Error> {
+        config.validate()?;
+        self.cache.configure(config);
+        Ok(())
+    }
 }
 
-impl FeatureFlag {
-    pub fn to_cache_key(&self) -> String {
-        use FeatureFlag::*;
-
-        match self {
-            Boolean { name, .. } => format!("boolean-{}", name),
-            Actor { name, target, .. } => format!("actor-{}-{}", target, name),
-            Group { name, .. } => format!("group-{}", name),
-            Time { name, .. } => format!("time-{}", name),
-            Percentage { name, .. } => format!("percentage-{}", name),
-            Empty => panic!("can not set this value"),
+#[cfg(test)]
+mod tests {
+    use super::Backend;
+    use crate::backend::{ChangeCallback, MockStorageBackend, StorageBackend};
+    use crate::config::CacheConfig;
+    use crate::{FeatureFlag, Flag};
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn change_of_other_process_evicts_flag() {
+        let subscriber: Arc<Mutex<Option<ChangeCallback>>> = Arc::new(Mutex::new(None));
+        let subscribed = subscriber.clone();
+
+        let mut mock = MockStorageBackend::new();
+        mock.expect_subscribe_changes().returning(move |on_change| {
+            *subscribed.lock().unwrap() = Some(on_change);
+            Ok(())
+        });
+        mock.expect_get_flag()
+            .times(5)
+            .returning(|name| Ok(Flag::new(name, vec![])));
+
+        let backend = Backend::new(Box::new(mock)).unwrap();
+
+        backend.get_flag("testing").unwrap();
+        backend.get_flag("testing").unwrap();
+
+        let on_change = subscriber.lock().unwrap().take().unwrap();
+        assert!(on_change(Some("testing")));
+
+        backend.get_flag("testing").unwrap();
+        backend.get_flag("other").unwrap();
+        assert!(on_change(None));
+
+        backend.get_flag("testing").unwrap();
+        backend.get_flag("other").unwrap();
+
+        drop(backend);
+        assert!(!on_change(Some("testing")));
+    }
+
+    fn stored_flag(name: &str) -> Flag {
+        Flag::new(
+            name,
+            vec![FeatureFlag::Boolean {
+                name: name.to_string(),
+                enabled: true,
+            }],
+        )
+    }
+
+    #[test]
+    fn flag_ttl_overrides_ttl() {
+        let mut mock = MockStorageBackend::new();
+        mock.expect_subscribe_changes().returning(|_| Ok(()));
+        mock.expect_get_flag()
+            .withf(|name| name == "kill_switch")
+            .times(2)
+            .returning(|name| Ok(stored_flag(name)));
+        mock.expect_get_flag()
+            .withf(|name| name == "static_flag")
+            .times(1)
+            .returning(|name| Ok(stored_flag(name)));
+
+        let config = CacheConfig::default().flag_ttl("kill_switch", Duration::from_secs(0));
+        let backend = Backend::with_config(Box::new(mock), config).unwrap();
+
+        for _ in 0..2 {
+            backend.get_flag("kill_switch").unwrap();
+            backend.get_flag("static_flag").unwrap();
         }
     }
+
+    #[test]
+    fn sub_second_ttl_is_cached() {
+        let mut mock = MockStorageBackend::new();
+        mock.expect_subscribe_changes().returning(|_| Ok(()));
+        mock.expect_get_flag()
+            .times(1)
+            .returning(|name| Ok(stored_flag(name)));
+
+        let config = CacheConfig::default().flag_ttl("kill_switch", Duration::from_millis(500));
+        assert_eq!(Duration::from_millis(500), config.ttl_for("kill_switch"));
+        let backend = Backend::with_config(Box::new(mock), config).unwrap();
+
+        for _ in 0..2 {
+            backend.get_flag("kill_switch").unwrap();
+        }
+
+        let negative = CacheConfig {
+            ttl: Some(-1.0),
+            ..CacheConfig::default()
+        };
+        assert!(backend.configure_cache(negative).is_err());
+    }
+
+    #[test]
+    fn empty_flags_are_not_cached_when_disabled() {
+        let mut mock = MockStorageBackend::new();
+        mock.expect_subscribe_changes().returning(|_| Ok(()));
+        mock.expect_get_flag()
+            .times(3)
+            .returning(|name| Ok(Flag::new(name, vec![])));
+
+        let backend = Backend::new(Box::new(mock)).unwrap();
+        backend.get_flag("testing").unwrap();
+        backend.get_flag("testing").unwrap();
+
+        let config = CacheConfig {
+            empty: Some(false),
+            ..CacheConfig::default()
+        };
+        backend.configure_cache(config).unwrap();
+        backend.get_flag("testing").unwrap();
+        backend.get_flag("testing").unwrap();
+    }
 }
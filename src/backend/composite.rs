@@ -0,0 +1,387 @@
+#[cfg(feature = "async")]
+use crate::backend::AsyncStorageBackend;
+use crate::backend::{ChangeCallback, FlagOutput, SetOutput, StorageBackend};
+use crate::config::{ConfigError, WritePolicy};
+use crate::{Error, FeatureFlag, Flag};
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// returns the output of the first backend that does not fail, or the error of the last one
+macro_rules! read_first {
+    ($backends:expr, $backend:ident => $read:expr) => {{
+        let mut last_error = None;
+        for $backend in $backends.iter() {
+            match $read {
+                Ok(output) => return Ok(output),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.expect("composite backend has at least one backend"))
+    }};
+}
+
+/// writes to the backends in order, stops at the first error and returns the output of the first backend,
+/// the backends that were written to before the error are not rolled back
+macro_rules! write_each {
+    ($backends:expr, $backend:ident => $write:expr) => {{
+        let mut output = None;
+        for $backend in $backends.iter() {
+            let written = $write?;
+            output.get_or_insert(written);
+        }
+        Ok(output.expect("composite backend has at least one backend"))
+    }};
+}
+
+/// chain of backends, reads from the first one that works and writes to the primary or to all of them
+///
+/// Reads only fall through to the next backend on an error, a flag that is not stored in the first backend is not stored.
+/// The first backend is the primary unless `with_primary` picks another one. With `WritePolicy::All` the primary is
+/// written first and then the others in order, every backend has to accept the write, so a read only backend like the
+/// file backend can only be used with `WritePolicy::Primary`. The writes are not a transaction, when one fails the
+/// backends before it keep the change.
+///
+/// ```toml
+/// [composite]
+/// # tried in this order, each backend is configured in its own section
+/// backends = ["postgres", "file"]
+/// # "primary" or "all"
+/// write = "primary"
+/// # written to first, defaults to the first of the backends
+/// # primary = "postgres"
+/// ```
+pub struct Backend {
+    backends: Vec<Box<dyn StorageBackend>>,
+    write: WritePolicy,
+    primary: usize,
+}
+
+impl Backend {
+    pub fn new(
+        backends: Vec<Box<dyn StorageBackend>>,
+        write: WritePolicy,
+    ) -> Result<Backend, Error> {
+        if backends.is_empty() {
+            return Err(no_backends());
+        }
+
+        Ok(Backend {
+            backends,
+            write,
+            primary: 0,
+        })
+    }
+
+    /// writes to the backend at the index `primary` instead of the first one
+    pub fn with_primary(mut self, primary: usize) -> Result<Backend, Error> {
+        self.primary = check_primary(primary, self.backends.len())?;
+        Ok(self)
+    }
+
+    /// the backends that are written to, the primary first
+    fn writers(&self) -> Vec<&dyn StorageBackend> {
+        writers(&self.backends, self.write, self.primary)
+    }
+}
+
+/// async version of the composite `Backend`
+#[cfg(feature = "async")]
+pub struct AsyncBackend {
+    backends: Vec<Box<dyn AsyncStorageBackend>>,
+    write: WritePolicy,
+    primary: usize,
+}
+
+#[cfg(feature = "async")]
+impl AsyncBackend {
+    pub fn new(
+        backends: Vec<Box<dyn AsyncStorageBackend>>,
+        write: WritePolicy,
+    ) -> Result<AsyncBackend, Error> {
+        if backends.is_empty() {
+            return Err(no_backends());
+        }
+
+        Ok(AsyncBackend {
+            backends,
+            write,
+            primary: 0,
+        })
+    }
+
+    /// writes to the backend at the index `primary` instead of the first one
+    pub fn with_primary(mut self, primary: usize) -> Result<AsyncBackend, Error> {
+        self.primary = check_primary(primary, self.backends.len())?;
+        Ok(self)
+    }
+
+    /// the backends that are written to, the primary first
+    fn writers(&self) -> Vec<&dyn AsyncStorageBackend> {
+        writers(&self.backends, self.write, self.primary)
+    }
+}
+
+fn check_primary(primary: usize, backends: usize) -> Result<usize, Error> {
+    if primary >= backends {
+        return Err(Error::Config(ConfigError::Message(format!(
+            "composite primary {} is out of range, there are {} backends",
+            primary, backends
+        ))));
+    }
+
+    Ok(primary)
+}
+
+fn writers<B: ?Sized>(backends: &[Box<B>], write: WritePolicy, primary: usize) -> Vec<&B> {
+    let primary_backend = std::iter::once(&*backends[primary]);
+    match write {
+        WritePolicy::Primary => primary_backend.collect(),
+        WritePolicy::All => primary_backend
+            .chain(
+                backends
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| *index != primary)
+                    .map(|(_, backend)| &**backend),
+            )
+            .collect(),
+    }
+}
+
+fn no_backends() -> Error {
+    Error::Config(ConfigError::Message(String::from(
+        "composite backend needs at least one backend",
+    )))
+}
+
+/// shares `on_change` between the backends, so a change that any of them notices is passed on
+fn share(on_change: ChangeCallback) -> impl Fn() -> ChangeCallback {
+    let on_change = Arc::new(Mutex::new(on_change));
+
+    move || {
+        let on_change = on_change.clone();
+        Box::new(move |flag_name| (on_change.lock().unwrap())(flag_name))
+    }
+}
+
+impl StorageBackend for Backend {
+    fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        read_first!(self.backends, backend => backend.get_flag(flag_name))
+    }
+
+    fn set(&self, flag: FeatureFlag) -> SetOutput {
+        write_each!(self.writers(), backend => backend.set(flag.clone()))
+    }
+
+    fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        write_each!(self.writers(), backend => backend.clear(flag_name))
+    }
+
+    fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        write_each!(self.writers(), backend => backend.clear_gate(gate.clone()))
+    }
+
+    fn list(&self) -> Result<HashSet<String>, Error> {
+        read_first!(self.backends, backend => backend.list())
+    }
+
+    fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        read_first!(self.backends, backend => backend.all_flags())
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "composite"
+    }
+
+    fn publish_change(&self, flag_name: &str) -> Result<(), Error> {
+        write_each!(self.writers(), backend => backend.publish_change(flag_name))
+    }
+
+    fn subscribe_changes(&self, on_change: ChangeCallback) -> Result<(), Error> {
+        let on_change = share(on_change);
+        for backend in self.backends.iter() {
+            backend.subscribe_changes(on_change())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl AsyncStorageBackend for AsyncBackend {
+    async fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        read_first!(self.backends, backend => backend.get_flag(flag_name).await)
+    }
+
+    async fn set(&self, flag: FeatureFlag) -> SetOutput {
+        write_each!(self.writers(), backend => backend.set(flag.clone()).await)
+    }
+
+    async fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        write_each!(self.writers(), backend => backend.clear(flag_name).await)
+    }
+
+    async fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        write_each!(self.writers(), backend => backend.clear_gate(gate.clone()).await)
+    }
+
+    async fn list(&self) -> Result<HashSet<String>, Error> {
+        read_first!(self.backends, backend => backend.list().await)
+    }
+
+    async fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        read_first!(self.backends, backend => backend.all_flags().await)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "composite"
+    }
+
+    async fn publish_change(&self, flag_name: &str) -> Result<(), Error> {
+        write_each!(self.writers(), backend => backend.publish_change(flag_name).await)
+    }
+
+    fn subscribe_changes(&self, on_change: ChangeCallback) -> Result<(), Error> {
+        let on_change = share(on_change);
+        for backend in self.backends.iter() {
+            backend.subscribe_changes(on_change())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Backend;
+    use crate::backend::memory;
+    use crate::backend::{MockStorageBackend, StorageBackend};
+    use crate::config::WritePolicy;
+    use crate::{Error, FeatureFlag};
+
+    fn boolean(enabled: bool) -> FeatureFlag {
+        FeatureFlag::Boolean {
+            name: String::from("testing"),
+            enabled,
+        }
+    }
+
+    #[test]
+    fn reads_fall_through_on_errors() {
+        let mut down = MockStorageBackend::new();
+        down.expect_get_flag()
+            .times(1)
+            .returning(|_| Err(Error::Custom(String::from("connection refused"))));
+
+        let fallback = memory::Backend::new();
+        fallback.set(boolean(true)).unwrap();
+
+        let backend = Backend::new(
+            vec![Box::new(down), Box::new(fallback)],
+            WritePolicy::Primary,
+        )
+        .unwrap();
+
+        assert!(backend.get_flag("testing").unwrap().enabled());
+    }
+
+    #[test]
+    fn write_policy_picks_the_backends() {
+        let primary = || {
+            let mut primary = MockStorageBackend::new();
+            primary.expect_set().times(1).returning(Ok);
+            primary
+        };
+
+        let mut untouched = MockStorageBackend::new();
+        untouched.expect_set().never();
+        let backend = Backend::new(
+            vec![Box::new(primary()), Box::new(untouched)],
+            WritePolicy::Primary,
+        )
+        .unwrap();
+        assert_eq!(boolean(true), backend.set(boolean(true)).unwrap());
+
+        let mut failing = MockStorageBackend::new();
+        failing
+            .expect_set()
+            .times(1)
+            .returning(|_| Err(Error::Custom(String::from("read only"))));
+        let backend = Backend::new(
+            vec![Box::new(primary()), Box::new(failing)],
+            WritePolicy::All,
+        )
+        .unwrap();
+        assert!(backend.set(boolean(true)).is_err());
+
+        assert!(Backend::new(vec![], WritePolicy::All).is_err());
+    }
+
+    #[test]
+    fn primary_is_written_first() {
+        let mut fallback = MockStorageBackend::new();
+        fallback.expect_set().never();
+        let mut primary = MockStorageBackend::new();
+        primary.expect_set().times(1).returning(Ok);
+        let backend = Backend::new(
+            vec![Box::new(fallback), Box::new(primary)],
+            WritePolicy::Primary,
+        )
+        .unwrap()
+        .with_primary(1)
+        .unwrap();
+        assert_eq!(boolean(true), backend.set(boolean(true)).unwrap());
+
+        // the primary fails first, so the other backend is not written to
+        let mut untouched = MockStorageBackend::new();
+        untouched.expect_set().never();
+        let mut failing = MockStorageBackend::new();
+        failing
+            .expect_set()
+            .times(1)
+            .returning(|_| Err(Error::Custom(String::from("connection refused"))));
+        let backend = Backend::new(
+            vec![Box::new(untouched), Box::new(failing)],
+            WritePolicy::All,
+        )
+        .unwrap()
+        .with_primary(1)
+        .unwrap();
+        assert!(backend.set(boolean(true)).is_err());
+
+        let backend = Backend::new(vec![Box::new(memory::Backend::new())], WritePolicy::All);
+        assert!(backend.unwrap().with_primary(1).is_err());
+    }
+
+    #[test]
+    fn from_config_chains_the_backends() {
+        use crate::config::{BackendType, CompositeConfig, RawConfig};
+        use crate::ErrorKind;
+
+        let with_primary = |primary: Option<BackendType>| RawConfig {
+            composite_config: Some(CompositeConfig {
+                backends: vec![BackendType::Null, BackendType::Memory],
+                write: WritePolicy::Primary,
+                primary,
+            }),
+            ..RawConfig::default()
+        };
+
+        let backend = crate::backend::from_config(&with_primary(None)).unwrap();
+
+        assert_eq!("composite", backend.backend_name());
+        assert!(backend.get_flag("testing").unwrap().is_empty());
+        assert!(backend.set(boolean(true)).is_err());
+
+        let backend =
+            crate::backend::from_config(&with_primary(Some(BackendType::Memory))).unwrap();
+        assert_eq!(boolean(true), backend.set(boolean(true)).unwrap());
+
+        let error = crate::backend::from_config(&with_primary(Some(BackendType::Redis)))
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(ErrorKind::Config, error.kind(), "{}", error);
+    }
+}
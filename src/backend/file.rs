@@ -0,0 +1,484 @@
+use crate::backend::{FlagOutput, SetOutput, StorageBackend};
+use crate::models::GroupSet;
+use crate::{Error, FeatureFlag, Flag};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// how long the parsed file is used before the file is checked for changes again
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// read only backend that loads the flags from a TOML, JSON or YAML file
+///
+/// The format is picked from the extension of the file. Every flag is a table with optional gates:
+///
+/// ```toml
+/// [new_checkout]
+/// boolean = false
+/// actors = { "user-1" = true, "user-2" = false }
+/// groups = { admins = true }
+/// percentage_of_actors = 0.25
+///
+/// [slow_rollout]
+/// percentage_of_time = 0.1
+/// ```
+///
+/// The parsed file is kept in memory and checked for changes every `CHECK_INTERVAL`, it is loaded again when it changed on disk.
+/// If it can not be parsed every call returns the error until it is fixed.
+/// The async backend runs the checks on the blocking threads of tokio.
+#[derive(Clone)]
+pub struct Backend {
+    path: Arc<PathBuf>,
+    loaded: Arc<RwLock<Loaded>>,
+    check_interval: Duration,
+}
+
+struct Loaded {
+    /// modification time and length of the file that is loaded
+    version: Option<(SystemTime, u64)>,
+    /// when the file was last checked for changes, `None` before the first check
+    checked: Option<Instant>,
+    flags: Result<HashMap<String, Flag>, Failure>,
+}
+
+/// why the file could not be loaded, kept to return the same error until the file is fixed
+#[derive(Debug, Clone)]
+enum Failure {
+    NotFound(String),
+    File(String),
+}
+
+impl Failure {
+    fn from_error(error: Error) -> Failure {
+        match error {
+            Error::NotFound(message) => Failure::NotFound(message),
+            Error::File { message, .. } => Failure::File(message),
+            other => Failure::File(other.to_string()),
+        }
+    }
+
+    fn to_error(&self, path: &Path) -> Error {
+        match self {
+            Failure::NotFound(message) => Error::NotFound(message.clone()),
+            Failure::File(message) => file_error(path, message),
+        }
+    }
+}
+
+/// a flag as it is written in the file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileFlag {
+    boolean: Option<bool>,
+    #[serde(default)]
+    actors: BTreeMap<String, bool>,
+    #[serde(default)]
+    groups: BTreeMap<String, bool>,
+    percentage_of_time: Option<f64>,
+    percentage_of_actors: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Result<Format, Error> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Ok(Format::Toml),
+            Some("json") => Ok(Format::Json),
+            Some("yaml") | Some("yml") => Ok(Format::Yaml),
+            _ => Err(file_error(
+                path,
+                "unknown format, use a .toml, .json, .yaml or .yml file",
+            )),
+        }
+    }
+}
+
+fn file_error<S: ToString>(path: &Path, message: S) -> Error {
+    Error::File {
+        path: path.to_path_buf(),
+        message: message.to_string(),
+    }
+}
+
+/// `Error::NotFound` when the file does not exist
+fn io_error(path: &Path, e: std::io::Error) -> Error {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => Error::NotFound(format!("{}: {}", path.display(), e)),
+        _ => file_error(path, e),
+    }
+}
+
+impl Backend {
+    /// loads the file, fails if it can not be read or parsed
+    pub fn establish<P: Into<PathBuf>>(path: P) -> Result<Backend, Error> {
+        let backend = Backend {
+            path: Arc::new(path.into()),
+            loaded: Arc::new(RwLock::new(Loaded {
+                version: None,
+                checked: None,
+                flags: Ok(HashMap::new()),
+            })),
+            check_interval: CHECK_INTERVAL,
+        };
+        Format::from_path(&backend.path)?;
+        backend.with_flags(|_| ())?;
+
+        Ok(backend)
+    }
+
+    /// checks the file for changes every `check_interval` instead of every `CHECK_INTERVAL`,
+    /// `Duration::ZERO` checks it on every call
+    pub fn with_check_interval(mut self, check_interval: Duration) -> Backend {
+        self.check_interval = check_interval;
+        self
+    }
+
+    /// creates the backend from a `file://<path>` url
+    pub fn from_url(url: &str) -> Result<Backend, Error> {
+        let path = url.strip_prefix("file://").unwrap_or(url);
+
+        Backend::establish(path)
+    }
+
+    /// runs `f` with the flags of the file, checks the file for changes when the `check_interval` passed
+    ///
+    /// Reads share the lock, only the check for changes takes it exclusively.
+    fn with_flags<T, F: FnOnce(&HashMap<String, Flag>) -> T>(&self, f: F) -> Result<T, Error> {
+        {
+            let loaded = self.loaded.read().unwrap();
+            if self.is_fresh(&loaded) {
+                return self.flags(&loaded).map(f);
+            }
+        }
+
+        let mut loaded = self.loaded.write().unwrap();
+        if !self.is_fresh(&loaded) {
+            self.reload(&mut loaded);
+        }
+
+        self.flags(&loaded).map(f)
+    }
+
+    fn is_fresh(&self, loaded: &Loaded) -> bool {
+        loaded
+            .checked
+            .is_some_and(|checked| checked.elapsed() < self.check_interval)
+    }
+
+    fn flags<'a>(&self, loaded: &'a Loaded) -> Result<&'a HashMap<String, Flag>, Error> {
+        loaded
+            .flags
+            .as_ref()
+            .map_err(|failure| failure.to_error(&self.path))
+    }
+
+    /// loads the file again if its modification time or length changed
+    fn reload(&self, loaded: &mut Loaded) {
+        loaded.checked = Some(Instant::now());
+
+        let version = match self.version() {
+            Ok(version) => version,
+            Err(e) => {
+                loaded.version = None;
+                loaded.flags = Err(Failure::from_error(e));
+                return;
+            }
+        };
+
+        if loaded.version != Some(version) {
+            loaded.version = Some(version);
+            loaded.flags = self.load().map_err(Failure::from_error);
+        }
+    }
+
+    fn version(&self) -> Result<(SystemTime, u64), Error> {
+        let metadata = std::fs::metadata(&*self.path).map_err(|e| io_error(&self.path, e))?;
+        let modified = metadata.modified().map_err(|e| io_error(&self.path, e))?;
+
+        Ok((modified, metadata.len()))
+    }
+
+    fn load(&self) -> Result<HashMap<String, Flag>, Error> {
+        let content = std::fs::read_to_string(&*self.path).map_err(|e| io_error(&self.path, e))?;
+
+        parse(Format::from_path(&self.path)?, &content)
+            .map_err(|message| file_error(&self.path, message))
+    }
+
+    fn read_only(&self) -> Error {
+        Error::Custom(format!(
+            "{}: file backend is read only, change the flags in the file",
+            self.path.display()
+        ))
+    }
+}
+
+/// parses the content of the file, the error contains the line and column if the parser reports them
+fn parse(format: Format, content: &str) -> Result<HashMap<String, Flag>, String> {
+    let file_flags: BTreeMap<String, FileFlag> = match format {
+        Format::Toml => toml::from_str(content).map_err(|e| e.to_string())?,
+        Format::Json => serde_json::from_str(content).map_err(|e| e.to_string())?,
+        Format::Yaml if content.trim().is_empty() => BTreeMap::new(),
+        Format::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string())?,
+    };
+
+    file_flags
+        .into_iter()
+        .map(|(name, file_flag)| {
+            let flag = to_flag(&name, file_flag)?;
+            Ok((name, flag))
+        })
+        .collect()
+}
+
+fn to_flag(name: &str, file_flag: FileFlag) -> Result<Flag, String> {
+    let mut gates = Vec::new();
+
+    if let Some(enabled) = file_flag.boolean {
+        gates.push(FeatureFlag::Boolean {
+            name: name.to_string(),
+            enabled,
+        });
+    }
+
+    for (actor_id, enabled) in file_flag.actors {
+        gates.push(FeatureFlag::Actor {
+            name: name.to_string(),
+            target: actor_id,
+            enabled,
+        });
+    }
+
+    for (group_name, enabled) in file_flag.groups {
+        gates.push(FeatureFlag::Group {
+            name: name.to_string(),
+            target: GroupSet::new(group_name),
+            enabled,
+        });
+    }
+
+    match (file_flag.percentage_of_time, file_flag.percentage_of_actors) {
+        (Some(_), Some(_)) => {
+            return Err(format!(
+                "flag {} can not have both percentage_of_time and percentage_of_actors",
+                name
+            ))
+        }
+        (Some(target), None) => gates.push(FeatureFlag::Time {
+            name: name.to_string(),
+            target,
+            enabled: true,
+        }),
+        (None, Some(target)) => gates.push(FeatureFlag::Percentage {
+            name: name.to_string(),
+            target,
+            enabled: true,
+        }),
+        (None, None) => (),
+    }
+
+    // the same checks as a write through the client, so the file can not hold gates the other backends refuse
+    for gate in &gates {
+        gate.validate().map_err(|e| e.to_string())?;
+    }
+
+    Ok(Flag::new(name, gates))
+}
+
+impl StorageBackend for Backend {
+    fn set(&self, _flag: FeatureFlag) -> SetOutput {
+        Err(self.read_only())
+    }
+
+    fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        self.with_flags(|flags| {
+            flags
+                .get(flag_name)
+                .cloned()
+                .unwrap_or_else(|| Flag::new(flag_name, vec![]))
+        })
+    }
+
+    fn clear(&self, _flag_name: &str) -> Result<(), Error> {
+        Err(self.read_only())
+    }
+
+    fn clear_gate(&self, _gate: FeatureFlag) -> Result<(), Error> {
+        Err(self.read_only())
+    }
+
+    fn list(&self) -> Result<HashSet<String>, Error> {
+        self.with_flags(|flags| flags.keys().cloned().collect())
+    }
+
+    fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        self.with_flags(|flags| {
+            let mut all_flags: Vec<Flag> = flags.values().cloned().collect();
+            all_flags.sort_by(|a, b| a.name.cmp(&b.name));
+            all_flags
+        })
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "file"
+    }
+}
+
+#[cfg(feature = "async")]
+blocking_async_backend!(Backend);
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Backend, Format};
+    use crate::backend::StorageBackend;
+    use crate::{ErrorKind, FeatureFlag};
+
+    use std::time::Duration;
+
+    const TOML: &str = r#"
+[new_checkout]
+boolean = false
+actors = { "user-1" = true }
+groups = { admins = true }
+percentage_of_actors = 0.25
+
+[slow_rollout]
+percentage_of_time = 0.1
+"#;
+
+    const JSON: &str = r#"{
+    "new_checkout": {
+        "boolean": false,
+        "actors": { "user-1": true },
+        "groups": { "admins": true },
+        "percentage_of_actors": 0.25
+    },
+    "slow_rollout": { "percentage_of_time": 0.1 }
+}"#;
+
+    const YAML: &str = r#"
+new_checkout:
+  boolean: false
+  actors:
+    user-1: true
+  groups:
+    admins: true
+  percentage_of_actors: 0.25
+slow_rollout:
+  percentage_of_time: 0.1
+"#;
+
+    #[test]
+    fn formats_give_the_same_flags() {
+        let toml = parse(Format::Toml, TOML).unwrap();
+
+        assert_eq!(toml, parse(Format::Json, JSON).unwrap());
+        assert_eq!(toml, parse(Format::Yaml, YAML).unwrap());
+
+        let flag = &toml["new_checkout"];
+        assert_eq!(4, flag.gates.len());
+        assert!(flag.enabled_for(&"user-1"));
+        assert!(!flag.enabled());
+        assert_eq!(
+            vec![FeatureFlag::Time {
+                name: String::from("slow_rollout"),
+                target: 0.1,
+                enabled: true,
+            }],
+            toml["slow_rollout"].gates
+        );
+    }
+
+    #[test]
+    fn parse_errors_contain_the_location() {
+        let error = parse(Format::Toml, "[testing]\nboolean = yes\n").unwrap_err();
+        assert!(error.contains("line 2"), "{}", error);
+
+        let error = parse(Format::Json, "{\n\"testing\": {\"boolen\": true}}").unwrap_err();
+        assert!(error.contains("unknown field `boolen`"), "{}", error);
+        assert!(error.contains("line 2"), "{}", error);
+
+        let error = parse(Format::Yaml, "testing:\n  boolean: [\n").unwrap_err();
+        assert!(error.contains("line"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_percentages_are_errors() {
+        for percentage in ["1.5", "1.0", "0.0", "-0.5"] {
+            let toml = format!("[testing]\npercentage_of_time = {}", percentage);
+            assert!(parse(Format::Toml, &toml).is_err(), "{}", percentage);
+        }
+        assert!(parse(Format::Toml, "[testing]\nactors = { \"\" = true }").is_err());
+        assert!(parse(
+            Format::Toml,
+            "[testing]\npercentage_of_time = 0.5\npercentage_of_actors = 0.5"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn reloads_the_file_when_it_changed() {
+        let path =
+            std::env::temp_dir().join(format!("fun-with-flags-{}-reload.toml", std::process::id()));
+        std::fs::write(&path, "[testing]\nboolean = true\n").unwrap();
+
+        let backend = Backend::establish(&path)
+            .unwrap()
+            .with_check_interval(Duration::ZERO);
+        assert!(backend.get_flag("testing").unwrap().enabled());
+        assert!(backend.set(FeatureFlag::Empty).is_err());
+
+        std::fs::write(
+            &path,
+            "[testing]\nboolean = false\n[other]\nboolean = true\n",
+        )
+        .unwrap();
+        assert!(!backend.get_flag("testing").unwrap().enabled());
+        assert_eq!(2, backend.list().unwrap().len());
+
+        std::fs::write(&path, "[testing]\nboolean = \n").unwrap();
+        let error = backend.get_flag("testing").unwrap_err().to_string();
+        assert!(error.contains(path.to_str().unwrap()), "{}", error);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            ErrorKind::NotFound,
+            backend.get_flag("testing").unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn file_is_checked_once_per_interval() {
+        let path = std::env::temp_dir().join(format!(
+            "fun-with-flags-{}-interval.toml",
+            std::process::id()
+        ));
+        std::fs::write(&path, "[testing]\nboolean = true\n").unwrap();
+
+        let backend = Backend::establish(&path)
+            .unwrap()
+            .with_check_interval(Duration::from_secs(3600));
+        std::fs::write(
+            &path,
+            "[testing]\nboolean = false\n[other]\nboolean = true\n",
+        )
+        .unwrap();
+        let cached = backend.get_flag("testing").unwrap();
+        let checked = backend
+            .with_check_interval(Duration::ZERO)
+            .get_flag("testing")
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(cached.enabled());
+        assert!(!checked.enabled());
+    }
+}
@@ -0,0 +1,279 @@
+use crate::backend::{FlagOutput, SetOutput, StorageBackend};
+use crate::error::ValidationError;
+use crate::{Error, FeatureFlag, Flag};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+/// backend that keeps the gates in memory, for tests and tools that run without a database
+///
+/// Stores the gates the same way as the Redis and Postgres backends,
+/// so a flag has one gate per actor and group and only one percentage gate.
+#[derive(Debug, Default)]
+pub struct Backend {
+    flags: RwLock<HashMap<String, Vec<FeatureFlag>>>,
+}
+
+impl Backend {
+    pub fn new() -> Backend {
+        Backend::default()
+    }
+}
+
+fn is_percentage(gate: &FeatureFlag) -> bool {
+    matches!(
+        gate,
+        FeatureFlag::Time { .. } | FeatureFlag::Percentage { .. }
+    )
+}
+
+/// the stored percentage gate with `enabled` set to false, keeps the percentage like the other backends do
+fn disable_percentage(gate: &FeatureFlag) -> FeatureFlag {
+    match gate {
+        FeatureFlag::Time { name, target, .. } => FeatureFlag::Time {
+            name: name.clone(),
+            target: *target,
+            enabled: false,
+        },
+        FeatureFlag::Percentage { name, target, .. } => FeatureFlag::Percentage {
+            name: name.clone(),
+            target: *target,
+            enabled: false,
+        },
+        other => other.clone(),
+    }
+}
+
+/// replaces the gate that is the `same` as `gate`, or adds it
+fn upsert(gates: &mut Vec<FeatureFlag>, gate: FeatureFlag) {
+    match gates.iter_mut().find(|stored| gate.same(stored)) {
+        Some(stored) => *stored = gate,
+        None => gates.push(gate),
+    }
+}
+
+/// sets the only percentage gate of the flag, disabling keeps the stored percentage
+fn set_percentage(gates: &mut Vec<FeatureFlag>, gate: FeatureFlag) -> FeatureFlag {
+    let stored = match gates.iter_mut().find(|stored| is_percentage(stored)) {
+        Some(stored) => stored,
+        None => {
+            gates.push(gate.clone());
+            return gate;
+        }
+    };
+
+    if *gate.enabled() {
+        *stored = gate;
+    } else {
+        *stored = disable_percentage(stored);
+    }
+
+    stored.clone()
+}
+
+impl StorageBackend for Backend {
+    fn set(&self, flag: FeatureFlag) -> SetOutput {
+        let flag_name = flag.name().to_string();
+        let no_gate = || {
+            Error::from(ValidationError::NoGate {
+                flag: flag_name.clone(),
+            })
+        };
+
+        if let FeatureFlag::Empty = flag {
+            return Err(no_gate());
+        }
+
+        let mut flags = self.flags.write().unwrap();
+        let gates = flags.entry(flag_name.clone()).or_default();
+
+        let stored = match flag {
+            FeatureFlag::Group { .. } => {
+                for gate in flag.split_groups() {
+                    upsert(gates, gate);
+                }
+                Flag::new(flag.name(), gates.clone()).find(&flag)
+            }
+            FeatureFlag::Time { .. } | FeatureFlag::Percentage { .. } => {
+                set_percentage(gates, flag)
+            }
+            _ => {
+                upsert(gates, flag.clone());
+                flag
+            }
+        };
+
+        if gates.is_empty() {
+            flags.remove(&flag_name);
+        }
+
+        match stored {
+            FeatureFlag::Empty => Err(no_gate()),
+            stored => Ok(stored),
+        }
+    }
+
+    fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        let flags = self.flags.read().unwrap();
+        let gates = flags.get(flag_name).cloned().unwrap_or_default();
+
+        Ok(Flag::new(flag_name, gates))
+    }
+
+    fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        self.flags.write().unwrap().remove(flag_name);
+
+        Ok(())
+    }
+
+    fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        let mut flags = self.flags.write().unwrap();
+
+        let gates = match flags.get_mut(gate.name()) {
+            Some(gates) => gates,
+            None => return Ok(()),
+        };
+
+        for gate in gate.split_groups() {
+            if is_percentage(&gate) {
+                gates.retain(|stored| !is_percentage(stored));
+            } else {
+                gates.retain(|stored| !gate.same(stored));
+            }
+        }
+
+        if gates.is_empty() {
+            flags.remove(gate.name());
+        }
+
+        Ok(())
+    }
+
+    fn list(&self) -> Result<HashSet<String>, Error> {
+        let flags = self.flags.read().unwrap();
+
+        Ok(flags.keys().cloned().collect())
+    }
+
+    fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        let flags = self.flags.read().unwrap();
+
+        let mut all_flags: Vec<Flag> = flags
+            .iter()
+            .map(|(name, gates)| Flag::new(name.clone(), gates.clone()))
+            .collect();
+        all_flags.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(all_flags)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "memory"
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl crate::backend::AsyncStorageBackend for Backend {
+    async fn set(&self, flag: FeatureFlag) -> SetOutput {
+        StorageBackend::set(self, flag)
+    }
+
+    async fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        StorageBackend::get_flag(self, flag_name)
+    }
+
+    async fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        StorageBackend::clear(self, flag_name)
+    }
+
+    async fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        StorageBackend::clear_gate(self, gate)
+    }
+
+    async fn list(&self) -> Result<HashSet<String>, Error> {
+        StorageBackend::list(self)
+    }
+
+    async fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        StorageBackend::all_flags(self)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "memory"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Backend;
+    use crate::backend::StorageBackend;
+    use crate::models::GroupSet;
+    use crate::FeatureFlag;
+
+    fn time(enabled: bool, target: f64) -> FeatureFlag {
+        FeatureFlag::Time {
+            name: String::from("testing"),
+            target,
+            enabled,
+        }
+    }
+
+    #[test]
+    fn disabling_percentage_keeps_the_percentage() {
+        let backend = Backend::new();
+
+        backend.set(time(true, 0.5)).unwrap();
+        let stored = backend.set(time(false, 0.0)).unwrap();
+
+        match stored {
+            FeatureFlag::Time {
+                target, enabled, ..
+            } => {
+                assert!(!enabled);
+                assert!((target - 0.5).abs() < f64::EPSILON);
+            }
+            other => panic!("expected a time gate, got {:?}", other),
+        }
+        assert_eq!(1, backend.get_flag("testing").unwrap().gates.len());
+    }
+
+    #[test]
+    fn percentage_of_actors_replaces_percentage_of_time() {
+        let backend = Backend::new();
+
+        backend.set(time(true, 0.5)).unwrap();
+        backend
+            .set(FeatureFlag::Percentage {
+                name: String::from("testing"),
+                target: 0.1,
+                enabled: true,
+            })
+            .unwrap();
+
+        let flag = backend.get_flag("testing").unwrap();
+        assert_eq!(1, flag.gates.len());
+        assert!(matches!(flag.gates[0], FeatureFlag::Percentage { .. }));
+    }
+
+    #[test]
+    fn clearing_the_last_gate_removes_the_flag() {
+        let backend = Backend::new();
+        let groups: GroupSet = vec![String::from("a"), String::from("b")]
+            .into_iter()
+            .collect();
+        let gate = FeatureFlag::Group {
+            name: String::from("testing"),
+            target: groups,
+            enabled: true,
+        };
+
+        backend.set(gate.clone()).unwrap();
+        assert_eq!(2, backend.get_flag("testing").unwrap().gates.len());
+        assert!(backend.list().unwrap().contains("testing"));
+
+        backend.clear_gate(gate).unwrap();
+        assert!(backend.get_flag("testing").unwrap().is_empty());
+        assert!(backend.list().unwrap().is_empty());
+    }
+}
@@ -1,50 +1,155 @@
-use crate::models::FeatureFlag;
+use crate::backend::{FlagOutput, SetOutput, StorageBackend};
+use crate::config::ConfigError;
+use crate::error::ErrorKind;
+use crate::{Error, FeatureFlag};
 
-use mockall::automock;
+use std::collections::HashSet;
 
-pub type DB = ();
-pub type DBConnection = Connection;
-pub type SetOutput = Result<FeatureFlag, ()>;
-pub type GetOutput = Result<FeatureFlag, ()>;
+/// backend that does not store anything, every call returns an error
+#[derive(Debug, Default)]
+pub struct Backend;
 
-pub struct Backend {}
+impl StorageBackend for Backend {
+    fn set(&self, _flag: FeatureFlag) -> SetOutput {
+        Err(Error::Custom(String::from(
+            "null backend can not set flags",
+        )))
+    }
+
+    fn get_flag(&self, _flag_name: &str) -> FlagOutput {
+        Err(Error::Custom(String::from(
+            "null backend can not get flags",
+        )))
+    }
+
+    fn clear(&self, _flag_name: &str) -> Result<(), Error> {
+        Err(Error::Custom(String::from(
+            "null backend can not clear flags",
+        )))
+    }
+
+    fn clear_gate(&self, _gate: FeatureFlag) -> Result<(), Error> {
+        Err(Error::Custom(String::from(
+            "null backend can not clear gates",
+        )))
+    }
 
-pub struct Connection {}
+    fn list(&self) -> Result<HashSet<String>, Error> {
+        Err(Error::Custom(String::from(
+            "null backend can not list flags",
+        )))
+    }
 
-impl Connection {
-    pub fn establish(_url: &str) -> Result<DBConnection, ()> {
-        Ok(Self {})
+    fn backend_name(&self) -> &'static str {
+        "null"
     }
 }
 
-#[automock]
-impl Backend {
-    pub fn set(_conn: &DBConnection, _flag: FeatureFlag) -> SetOutput {
-        Err(())
+/// backend of a default client that could not be created, every call returns the error it failed with
+pub(crate) struct Unavailable {
+    kind: ErrorKind,
+    reason: String,
+}
+
+impl Unavailable {
+    pub(crate) fn new(error: &Error) -> Unavailable {
+        Unavailable {
+            kind: error.kind(),
+            reason: error.to_string(),
+        }
+    }
+
+    /// an error of the same kind as the one the client failed with
+    fn error(&self) -> Error {
+        let message = format!("the default client could not be created: {}", self.reason);
+
+        match self.kind {
+            ErrorKind::Connection => Error::Connection(message.into()),
+            ErrorKind::Config => Error::Config(ConfigError::Message(message)),
+            ErrorKind::NotFound => Error::NotFound(message),
+            _ => Error::Custom(message),
+        }
+    }
+}
+
+impl StorageBackend for Unavailable {
+    fn set(&self, _flag: FeatureFlag) -> SetOutput {
+        Err(self.error())
+    }
+
+    fn get_flag(&self, _flag_name: &str) -> FlagOutput {
+        Err(self.error())
+    }
+
+    fn clear(&self, _flag_name: &str) -> Result<(), Error> {
+        Err(self.error())
+    }
+
+    fn clear_gate(&self, _gate: FeatureFlag) -> Result<(), Error> {
+        Err(self.error())
     }
 
-    pub fn get(_conn: &DBConnection, _flag: FeatureFlag) -> GetOutput {
-        Err(())
+    fn list(&self) -> Result<HashSet<String>, Error> {
+        Err(self.error())
     }
 
-    pub fn backend_name() -> &'static str {
+    fn backend_name(&self) -> &'static str {
         "null"
     }
 }
 
-// use mock::MockBackend;
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl crate::backend::AsyncStorageBackend for Backend {
+    async fn set(&self, flag: FeatureFlag) -> SetOutput {
+        StorageBackend::set(self, flag)
+    }
+
+    async fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        StorageBackend::get_flag(self, flag_name)
+    }
+
+    async fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        StorageBackend::clear(self, flag_name)
+    }
+
+    async fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        StorageBackend::clear_gate(self, gate)
+    }
+
+    async fn list(&self) -> Result<HashSet<String>, Error> {
+        StorageBackend::list(self)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "null"
+    }
+}
 
-// let mut mock = MockBackend::new();
-// mock.expect_connection_name()
-//     .returning(|| "xd");
-// assert_eq!(5, mock::connection_name(4));
-// lazy_static::lazy_static! {
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl crate::backend::AsyncStorageBackend for Unavailable {
+    async fn set(&self, flag: FeatureFlag) -> SetOutput {
+        StorageBackend::set(self, flag)
+    }
 
-//     let mock = MockBackend::default();
+    async fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        StorageBackend::get_flag(self, flag_name)
+    }
 
-//     let ctx = MockBackend::backend_name_context();
-//     ctx.expect().returning(|| "xd");
+    async fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        StorageBackend::clear(self, flag_name)
+    }
 
-//     assert_eq!("topper", Backend::backend_name());
+    async fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        StorageBackend::clear_gate(self, gate)
+    }
 
-// }
+    async fn list(&self) -> Result<HashSet<String>, Error> {
+        StorageBackend::list(self)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "null"
+    }
+}
@@ -1,125 +1,119 @@
-use crate::models::{FeatureFlag, GroupSet, RawFeatureFlag};
+use crate::backend::sql::{group_by_flag, qualified, quote, stored_groups, Statements, TABLE};
+use crate::backend::{
+    pool_builder, spawn_listener, ChangeCallback, FlagOutput, SetOutput, StorageBackend,
+    CHANGES_CHANNEL,
+};
+use crate::config::{ConfigError, PostgresConfig, SslMode};
+use crate::models::{FeatureFlag, Flag, RawFeatureFlag};
 use crate::Error;
 
+use std::convert::TryFrom;
+
+use postgres::fallible_iterator::FallibleIterator;
 use postgres::types::ToSql;
+#[cfg(not(feature = "postgres-tls"))]
 use postgres::NoTls;
 use postgres::Row;
 
 use r2d2_postgres::PostgresConnectionManager;
 
-use std::sync::Mutex;
-use state::Storage;
-
-pub type DB = ();
-pub type DBConnection = Connection;
-pub type SetOutput = Result<FeatureFlag, Error>;
-pub type GetOutput = Result<FeatureFlag, Error>;
-pub type ConnectionResult =
-    Result<r2d2::PooledConnection<PostgresConnectionManager<NoTls>>, Error>;
-type Pool = r2d2::Pool<PostgresConnectionManager<NoTls>>;
-
-// #[derive(Debug)]
-// pub struct Error(String);
-
-// impl std::fmt::Display for Error {
-//     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-//         write!(f, "{}", self.0)
-//     }
-// }
-
-// impl From<r2d2::Error> for Error {
-//     fn from(e: r2d2::Error) -> Self {
-//         Error(e.to_string())
-//     }
-// }
-
-// impl From<postgres::Error> for Error {
-//     fn from(e: postgres::Error) -> Self {
-//         Error(e.to_string())
-//     }
-// }
-
-lazy_static::lazy_static! {
-    static ref GLOBAL_POOL: Storage<Mutex<Pool>> = Storage::new();
+use std::collections::HashSet;
+use std::time::Duration;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "postgres-tls")] {
+        /// connects with TLS when the `sslmode` asks for it
+        pub type Tls = postgres_native_tls::MakeTlsConnector;
+    } else {
+        /// without the `postgres-tls` feature the connections are not encrypted
+        pub type Tls = NoTls;
+    }
 }
 
-pub struct Connection {
-    config: String
-    // pub pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+pub type ConnectionResult = Result<r2d2::PooledConnection<PostgresConnectionManager<Tls>>, Error>;
+type Pool = r2d2::Pool<PostgresConnectionManager<Tls>>;
+
+/// the default size of an r2d2 pool, the async backend uses the same
+pub(crate) const MAX_SIZE: u32 = 10;
+
+pub struct Backend {
+    pool: Pool,
+    config: postgres::Config,
+    tls: Tls,
+    statements: Statements,
+    channel: String,
 }
 
-impl Connection {
-    pub fn establish(url: &str) -> Result<Connection, Error> {
-        // let manager = PostgresConnectionManager::new(url.parse()?, NoTls);
-        // let pool = r2d2::Pool::new(manager)?;
-        // Ok(Connection { pool })
-        if GLOBAL_POOL.try_get().is_some() {
-            Ok(Connection{config: url.to_string()})
-        } else{ 
-            let manager = PostgresConnectionManager::new(url.parse()?, NoTls);
-            let pool = r2d2::Pool::new(manager)?;
-            GLOBAL_POOL.set(Mutex::new(pool));
-            Self::establish(url)
-        }
+impl Backend {
+    pub fn establish(url: &str) -> Result<Backend, Error> {
+        Backend::with_config(&PostgresConfig::new(url))
     }
-}
 
-pub struct Backend {}
+    /// creates the backend from the `[postgres]` section, the TLS settings need the `postgres-tls` feature
+    pub fn with_config(postgres_config: &PostgresConfig) -> Result<Backend, Error> {
+        let statements = statements(postgres_config)?;
+        let channel = changes_channel(postgres_config)?;
+        let (config, tls) = connection(postgres_config)?;
+        let manager = PostgresConnectionManager::new(config.clone(), connector(&tls));
+        let pool = pool_builder(&postgres_config.pool, MAX_SIZE)?.build(manager)?;
+        Ok(Backend {
+            pool,
+            config,
+            tls,
+            statements,
+            channel,
+        })
+    }
 
-impl Backend {
-    pub fn set(conn: &DBConnection, flag: FeatureFlag) -> SetOutput {
-        let mut conn = Self::create_conn(conn)?;
+    pub fn create_conn(&self) -> ConnectionResult {
+        let conn = self.pool.get()?;
+        Ok(conn)
+    }
+}
+
+impl StorageBackend for Backend {
+    fn set(&self, flag: FeatureFlag) -> SetOutput {
+        let mut conn = self.create_conn()?;
 
         use FeatureFlag::*;
 
-        let insertable = flag.to_row();
+        if let Group { .. } = flag {
+            return set_groups(&mut conn, &self.statements, &flag);
+        }
+
+        let insertable = flag.to_row()?;
         let db_result = match flag {
             Percentage { .. } | Time { .. } => {
                 let result = if insertable.enabled {
-                    let update_enable = r#"UPDATE "fun_with_flags_toggles" 
-                SET "target" = $1, "enabled" = $2 
-                WHERE "fun_with_flags_toggles"."flag_name" = $3 AND "fun_with_flags_toggles"."gate_type" = $4 
-                RETURNING "fun_with_flags_toggles"."id", "fun_with_flags_toggles"."flag_name", "fun_with_flags_toggles"."gate_type", "fun_with_flags_toggles"."target", "fun_with_flags_toggles"."enabled""#;
                     let arg_enable1: Vec<&(dyn ToSql + Sync)> = vec![
                         &insertable.target,
                         &insertable.enabled,
                         &insertable.flag_name,
                         &insertable.gate_type,
                     ];
-                    conn.query_opt(update_enable, &arg_enable1)?
+                    conn.query_opt(self.statements.update_percentage_enable(), &arg_enable1)?
                 } else {
-                    let update_disable = r#"UPDATE "fun_with_flags_toggles" 
-                SET "enabled" = $1 
-                WHERE "fun_with_flags_toggles"."flag_name" = $2 AND "fun_with_flags_toggles"."gate_type" = $3 
-                RETURNING "fun_with_flags_toggles"."id", "fun_with_flags_toggles"."flag_name", "fun_with_flags_toggles"."gate_type", "fun_with_flags_toggles"."target", "fun_with_flags_toggles"."enabled""#;
                     let arg_disable1: Vec<&(dyn ToSql + Sync)> = vec![
                         &insertable.enabled,
                         &insertable.flag_name,
                         &insertable.gate_type,
                     ];
-                    conn.query_opt(update_disable, &arg_disable1)?
+                    conn.query_opt(self.statements.update_percentage_disable(), &arg_disable1)?
                 };
 
-                if result.is_none() {
-                    let insert = r#"INSERT INTO "fun_with_flags_toggles" ("flag_name", "gate_type", "target", "enabled") 
-                VALUES ($1, $2, $3, $4) 
-                RETURNING "fun_with_flags_toggles"."id", "fun_with_flags_toggles"."flag_name", "fun_with_flags_toggles"."gate_type", "fun_with_flags_toggles"."target", "fun_with_flags_toggles"."enabled""#;
+                if let Some(row) = result {
+                    row
+                } else {
                     let arg2: Vec<&(dyn ToSql + Sync)> = vec![
                         &insertable.flag_name,
                         &insertable.gate_type,
                         &insertable.target,
                         &insertable.enabled,
                     ];
-                    conn.query_one(insert, &arg2)?
-                } else {
-                    result.unwrap()
+                    conn.query_one(self.statements.insert_gate(), &arg2)?
                 }
             }
             _ => {
-                let insert = r#"INSERT INTO "fun_with_flags_toggles" ("flag_name", "gate_type", "target", "enabled") 
-            VALUES ($1, $2, $3, $4) ON CONFLICT ("flag_name", "gate_type", "target") 
-            DO UPDATE SET "enabled" = $5 
-            RETURNING "fun_with_flags_toggles"."id", "fun_with_flags_toggles"."flag_name", "fun_with_flags_toggles"."gate_type", "fun_with_flags_toggles"."target", "fun_with_flags_toggles"."enabled""#;
                 let args: Vec<&(dyn ToSql + Sync)> = vec![
                     &insertable.flag_name,
                     &insertable.gate_type,
@@ -127,158 +121,564 @@ impl Backend {
                     &insertable.enabled,
                     &insertable.enabled,
                 ];
-                conn.query_one(insert, &args)?
+                conn.query_one(self.statements.upsert_gate(), &args)?
             }
         };
 
-        Ok(FeatureFlag::from_row(db_result))
+        FeatureFlag::from_row(db_result)
     }
 
-    pub fn get(conn: &DBConnection, flag: FeatureFlag) -> GetOutput {
-        let mut conn = Self::create_conn(conn).unwrap();
+    fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        let mut conn = self.create_conn()?;
 
-        use FeatureFlag::*;
+        let rows = conn.query(self.statements.select_flag(), &[&flag_name])?;
 
-        let insertable = flag.to_row();
-        if let Some(db_result) = match flag {
-            Time { .. } | Percentage { .. } | Group { .. } => {
-                let query = r#"SELECT "fun_with_flags_toggles"."id", "fun_with_flags_toggles"."flag_name", "fun_with_flags_toggles"."gate_type", "fun_with_flags_toggles"."target", "fun_with_flags_toggles"."enabled" 
-                FROM "fun_with_flags_toggles" WHERE "fun_with_flags_toggles"."flag_name" = $1 AND "fun_with_flags_toggles"."gate_type" = $2"#;
-                let arguments: Vec<&(dyn ToSql + Sync)> =
-                    vec![&insertable.flag_name, &insertable.gate_type];
-                conn.query_opt(query, &arguments)?
-            }
+        Flag::decode(flag_name, rows.into_iter().map(FeatureFlag::from_row))
+    }
 
-            _ => {
-                let query = r#"SELECT "fun_with_flags_toggles"."id", "fun_with_flags_toggles"."flag_name", "fun_with_flags_toggles"."gate_type", "fun_with_flags_toggles"."target", "fun_with_flags_toggles"."enabled" 
-                FROM "fun_with_flags_toggles" WHERE "fun_with_flags_toggles"."flag_name" = $1 AND "fun_with_flags_toggles"."gate_type" = $2 AND "fun_with_flags_toggles"."target" = $3"#;
-                let arguments: Vec<&(dyn ToSql + Sync)> = vec![
-                    &insertable.flag_name,
-                    &insertable.gate_type,
-                    &insertable.target,
-                ];
-                conn.query_opt(query, &arguments)?
-            }
-        } {
-            Ok(FeatureFlag::from_row(db_result))
-        } else {
-            Ok(FeatureFlag::Empty)
+    fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        let mut conn = self.create_conn()?;
+
+        conn.execute(self.statements.delete_flag(), &[&flag_name])?;
+
+        Ok(())
+    }
+
+    fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        let mut conn = self.create_conn()?;
+
+        use FeatureFlag::*;
+
+        let mut transaction = conn.transaction()?;
+        for gate in gate.split_groups() {
+            let row = gate.to_row()?;
+            match gate {
+                Time { .. } | Percentage { .. } => transaction.execute(
+                    self.statements.delete_gate_by_type(),
+                    &[&row.flag_name, &row.gate_type],
+                )?,
+                _ => transaction.execute(
+                    self.statements.delete_gate_by_target(),
+                    &[&row.flag_name, &row.gate_type, &row.target],
+                )?,
+            };
         }
+        transaction.commit()?;
+
+        Ok(())
     }
 
-    pub fn backend_name() -> &'static str {
+    fn list(&self) -> Result<HashSet<String>, Error> {
+        let mut conn = self.create_conn()?;
+
+        let names = conn
+            .query(self.statements.select_flag_names(), &[])?
+            .into_iter()
+            .map(|row| row.try_get("flag_name"))
+            .collect::<Result<_, _>>()?;
+
+        Ok(names)
+    }
+
+    fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        let mut conn = self.create_conn()?;
+
+        let rows = conn.query(self.statements.select_all_gates(), &[])?;
+
+        group_by_flag(rows.into_iter().map(FeatureFlag::from_row))
+    }
+
+    fn backend_name(&self) -> &'static str {
         "postgres"
     }
 
-    pub fn create_conn(config: &DBConnection) -> ConnectionResult {
-        // let pool = pool.pool.clone();
-        // pool.get()
-        if let Some(pool) = GLOBAL_POOL.try_get() {
-            let locked_pool = pool.lock().unwrap();
-            let cloned_pool = locked_pool.clone();
-            let conn = cloned_pool.get()?;
-            Ok(conn)
-        } else{ 
-            DBConnection::establish(&config.config)?;
-            Self::create_conn(config)
+    /// the trigger from `notify_migration` sends the notifications, so every write is published already
+    fn subscribe_changes(&self, on_change: ChangeCallback) -> Result<(), Error> {
+        spawn_notify_listener(
+            self.config.clone(),
+            connector(&self.tls),
+            self.channel.clone(),
+            on_change,
+        )
+    }
+}
+
+/// the statements for the `table` and `schema` of the section
+pub(crate) fn statements(postgres_config: &PostgresConfig) -> Result<Statements, Error> {
+    let schema = postgres_config.schema.as_deref();
+    for (key, name) in [("table", Some(postgres_config.table())), ("schema", schema)] {
+        if name.is_some_and(str::is_empty) {
+            return Err(Error::Config(ConfigError::Message(format!(
+                "postgres {} can not be empty",
+                key
+            ))));
         }
     }
+
+    Ok(Statements::new(schema, postgres_config.table()))
+}
+
+/// the longest channel postgres accepts, `LISTEN` cuts longer names but `pg_notify` rejects them
+const MAX_CHANNEL_LENGTH: usize = 63;
+
+/// the channel the trigger of the table sends the changes on, `fun_with_flags_changes` for the table of the
+/// elixir library and `<table>_changes` or `<schema>_<table>_changes` for the others, so they do not share changes
+pub(crate) fn changes_channel(postgres_config: &PostgresConfig) -> Result<String, Error> {
+    let channel = match (postgres_config.schema.as_deref(), postgres_config.table()) {
+        (None, TABLE) => String::from(CHANGES_CHANNEL),
+        (None, table) => format!("{}_changes", table),
+        (Some(schema), table) => format!("{}_{}_changes", schema, table),
+    };
+    if channel.len() > MAX_CHANNEL_LENGTH {
+        return Err(Error::Config(ConfigError::Message(format!(
+            "postgres channel {} is longer than {} bytes, use a shorter table or schema",
+            channel, MAX_CHANNEL_LENGTH
+        ))));
+    }
+
+    Ok(channel)
+}
+
+/// The trigger that sends the changes of the table of the section to the listeners of the other processes.
+///
+/// For the default table this is [migrations/postgres_notify/up.sql](../master/migrations/postgres_notify/up.sql),
+/// when `table` or `schema` are set run this instead, it notifies on the channel of the table.
+/// The trigger is called `<table>_changed` and calls the `<table>_notify_change` function.
+pub fn notify_migration(postgres_config: &PostgresConfig) -> Result<String, Error> {
+    statements(postgres_config)?;
+    let channel = changes_channel(postgres_config)?;
+    let schema = postgres_config.schema.as_deref();
+    let table = postgres_config.table();
+    let function = match (schema, table) {
+        (None, TABLE) => qualified(None, "fun_with_flags_notify_change"),
+        (schema, table) => qualified(schema, &format!("{}_notify_change", table)),
+    };
+    let channel = format!("'{}'", channel.replace('\'', "''"));
+
+    Ok(format!(
+        r#"CREATE FUNCTION {function}() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        PERFORM pg_notify({channel}, OLD.flag_name);
+    ELSE
+        PERFORM pg_notify({channel}, NEW.flag_name);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER {trigger}
+AFTER INSERT OR UPDATE OR DELETE ON {table}
+FOR EACH ROW EXECUTE FUNCTION {function}();
+"#,
+        function = function,
+        channel = channel,
+        trigger = quote(&format!("{}_changed", table)),
+        table = qualified(schema, table),
+    ))
+}
+
+/// the settings of the connections and the connector for the `sslmode`,
+/// the `sslmode` of the section overrides the one of the url
+///
+/// The `query_timeout` is the `statement_timeout` of the session, so the server cancels slow queries.
+pub(crate) fn connection(
+    postgres_config: &PostgresConfig,
+) -> Result<(postgres::Config, Tls), Error> {
+    use postgres::config::SslMode as UrlSslMode;
+
+    let mut config: postgres::Config = postgres_config.url.parse()?;
+
+    let sslmode = match (postgres_config.sslmode, config.get_ssl_mode()) {
+        (Some(sslmode), _) => sslmode,
+        (None, UrlSslMode::Disable) => SslMode::Disable,
+        (None, UrlSslMode::Require) => SslMode::Require,
+        (None, _) => SslMode::Prefer,
+    };
+    config.ssl_mode(match sslmode {
+        SslMode::Disable => UrlSslMode::Disable,
+        SslMode::Prefer => UrlSslMode::Prefer,
+        SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => UrlSslMode::Require,
+    });
+
+    if let Some(connection_timeout) = postgres_config.pool.connection_timeout() {
+        config.connect_timeout(connection_timeout);
+    }
+    if let Some(query_timeout) = postgres_config.pool.query_timeout() {
+        let options = statement_timeout(config.get_options(), query_timeout);
+        config.options(&options);
+    }
+
+    Ok((config, tls(postgres_config, sslmode)?))
+}
+
+/// adds the `statement_timeout` to the options of the url, at least a millisecond as 0 disables it
+fn statement_timeout(options: Option<&str>, query_timeout: Duration) -> String {
+    let setting = format!("-c statement_timeout={}", query_timeout.as_millis().max(1));
+    match options {
+        Some(options) if !options.is_empty() => format!("{} {}", options, setting),
+        _ => setting,
+    }
+}
+
+/// a connector for another pool or connection, only the TLS connector is not `Copy`
+#[allow(clippy::clone_on_copy)]
+pub(crate) fn connector(tls: &Tls) -> Tls {
+    tls.clone()
+}
+
+/// the connector that verifies the certificate like libpq does for the `sslmode`
+#[cfg(feature = "postgres-tls")]
+fn tls(postgres_config: &PostgresConfig, sslmode: SslMode) -> Result<Tls, Error> {
+    use native_tls::{Certificate, Identity, TlsConnector};
+
+    let mut builder = TlsConnector::builder();
+    builder
+        .danger_accept_invalid_certs(!matches!(sslmode, SslMode::VerifyCa | SslMode::VerifyFull))
+        .danger_accept_invalid_hostnames(sslmode != SslMode::VerifyFull);
+
+    if let Some(path) = &postgres_config.sslrootcert {
+        let certificates =
+            Certificate::stack_from_pem(&read(path)?).map_err(|e| file_error(path, e))?;
+        if certificates.is_empty() {
+            return Err(file_error(path, "no PEM certificates found"));
+        }
+        for certificate in certificates {
+            builder.add_root_certificate(certificate);
+        }
+    }
+
+    match (&postgres_config.sslcert, &postgres_config.sslkey) {
+        (Some(cert), Some(key)) => {
+            let identity =
+                Identity::from_pkcs8(&read(cert)?, &read(key)?).map_err(|e| file_error(cert, e))?;
+            builder.identity(identity);
+        }
+        (None, None) => (),
+        _ => {
+            return Err(Error::Config(ConfigError::Message(String::from(
+                "postgres sslcert and sslkey have to be set together",
+            ))))
+        }
+    }
+
+    let connector = builder
+        .build()
+        .map_err(|e| Error::Config(ConfigError::Foreign(Box::new(e))))?;
+
+    Ok(postgres_native_tls::MakeTlsConnector::new(connector))
+}
+
+/// without the `postgres-tls` feature only the sslmodes that do not need TLS work
+#[cfg(not(feature = "postgres-tls"))]
+fn tls(postgres_config: &PostgresConfig, sslmode: SslMode) -> Result<Tls, Error> {
+    let files = [
+        &postgres_config.sslrootcert,
+        &postgres_config.sslcert,
+        &postgres_config.sslkey,
+    ];
+
+    match sslmode {
+        SslMode::Disable | SslMode::Prefer if files.iter().all(|file| file.is_none()) => Ok(NoTls),
+        _ => Err(Error::Config(ConfigError::Message(format!(
+            "postgres sslmode {:?} and the certificate settings need the postgres-tls feature",
+            sslmode
+        )))),
+    }
+}
+
+#[cfg(feature = "postgres-tls")]
+fn read(path: &str) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).map_err(|e| file_error(path, e))
+}
+
+#[cfg(feature = "postgres-tls")]
+fn file_error<E: ToString>(path: &str, e: E) -> Error {
+    Error::File {
+        path: path.into(),
+        message: e.to_string(),
+    }
+}
+
+/// listens for the notifications of the trigger on its own connection in a background thread,
+/// reconnects when the connection drops
+pub(crate) fn spawn_notify_listener(
+    config: postgres::Config,
+    tls: Tls,
+    channel: String,
+    on_change: ChangeCallback,
+) -> Result<(), Error> {
+    spawn_listener(on_change, move |on_change| {
+        listen(&config, connector(&tls), &channel, on_change)
+    })
+}
+
+/// returns `Ok` when `on_change` asked to stop listening
+fn listen(
+    config: &postgres::Config,
+    tls: Tls,
+    channel: &str,
+    on_change: &ChangeCallback,
+) -> Result<(), Error> {
+    let mut client = config.connect(tls)?;
+    client.batch_execute(&format!("LISTEN {}", quote(channel)))?;
+
+    if !on_change(None) {
+        return Ok(());
+    }
+
+    let mut notifications = client.notifications();
+    let mut notifications = notifications.blocking_iter();
+
+    while let Some(notification) = notifications.next()? {
+        if !on_change(Some(notification.payload())) {
+            return Ok(());
+        }
+    }
+
+    Err(Error::Connection(
+        String::from("postgres closed the change listener connection").into(),
+    ))
+}
+
+/// upserts one row per group in a single transaction
+fn set_groups(
+    conn: &mut postgres::Client,
+    statements: &Statements,
+    flag: &FeatureFlag,
+) -> SetOutput {
+    let mut transaction = conn.transaction()?;
+
+    let mut gates = Vec::new();
+    for gate in flag.split_groups() {
+        let insertable = gate.to_row()?;
+        let args: Vec<&(dyn ToSql + Sync)> = vec![
+            &insertable.flag_name,
+            &insertable.gate_type,
+            &insertable.target,
+            &insertable.enabled,
+            &insertable.enabled,
+        ];
+        gates.push(FeatureFlag::from_row(
+            transaction.query_one(statements.upsert_gate(), &args)?,
+        )?);
+    }
+
+    transaction.commit()?;
+
+    stored_groups(flag, gates)
 }
 
 impl FeatureFlag {
-    pub fn to_row(&self) -> RawFeatureFlag {
-        use FeatureFlag::*;
+    /// the gate that is stored in the row, fails with `Error::Decode` if it can not be decoded
+    pub fn from_row(row: Row) -> Result<FeatureFlag, Error> {
+        FeatureFlag::try_from(RawFeatureFlag {
+            flag_name: row.try_get("flag_name")?,
+            gate_type: row.try_get("gate_type")?,
+            target: row.try_get("target")?,
+            enabled: row.try_get("enabled")?,
+        })
+    }
+}
 
-        match self {
-            Boolean { name, enabled } => RawFeatureFlag {
-                flag_name: name.to_string(),
-                gate_type: "boolean".to_string(),
-                target: "_fwf_none".to_string(),
-                enabled: *enabled,
-            },
-            Actor {
-                name,
-                target,
-                enabled,
-            } => RawFeatureFlag {
-                flag_name: name.to_string(),
-                gate_type: "actor".to_string(),
-                target: target.to_string(),
-                enabled: *enabled,
-            },
-            Group {
-                name,
-                target,
-                enabled,
-            } => RawFeatureFlag {
-                flag_name: name.to_string(),
-                gate_type: "group".to_string(),
-                target: target.to_optional_string().unwrap_or("").to_string(),
-                enabled: *enabled,
+#[cfg(test)]
+mod tests {
+    use super::{changes_channel, connection, notify_migration, statements, Backend};
+    use crate::backend::CHANGES_CHANNEL;
+    use crate::config::{PoolConfig, PostgresConfig, SslMode};
+    use crate::ErrorKind;
+
+    use postgres::config::SslMode as UrlSslMode;
+
+    use std::time::Duration;
+
+    fn with_sslmode(url: &str, sslmode: Option<SslMode>) -> PostgresConfig {
+        PostgresConfig {
+            sslmode,
+            ..PostgresConfig::new(url)
+        }
+    }
+
+    #[test]
+    fn sslmode_of_the_section_overrides_the_url() {
+        let (config, _) = connection(&PostgresConfig::new("postgres://localhost")).unwrap();
+        assert_eq!(UrlSslMode::Prefer, config.get_ssl_mode());
+
+        let (config, _) = connection(&with_sslmode(
+            "postgres://localhost?sslmode=prefer",
+            Some(SslMode::Disable),
+        ))
+        .unwrap();
+        assert_eq!(UrlSslMode::Disable, config.get_ssl_mode());
+    }
+
+    #[test]
+    fn pool_settings_are_applied_to_the_connections() {
+        let with_pool = |pool: PoolConfig| PostgresConfig {
+            pool,
+            ..PostgresConfig::new("postgres://localhost?options=-c%20search_path%3Dflags")
+        };
+
+        let (config, _) = connection(&with_pool(PoolConfig {
+            connection_timeout: Some(2.0),
+            query_timeout: Some(0.25),
+            ..PoolConfig::default()
+        }))
+        .unwrap();
+        assert_eq!(Some(&Duration::from_secs(2)), config.get_connect_timeout());
+        assert_eq!(
+            Some("-c search_path=flags -c statement_timeout=250"),
+            config.get_options()
+        );
+
+        // the pool is checked before it connects
+        for pool in [
+            PoolConfig {
+                max_size: Some(0),
+                ..PoolConfig::default()
             },
-            Time {
-                name,
-                target,
-                enabled,
-            } => RawFeatureFlag {
-                flag_name: name.to_string(),
-                gate_type: "percentage".to_string(),
-                target: format!("time/{}", target),
-                enabled: *enabled,
+            PoolConfig {
+                max_size: Some(2),
+                min_idle: Some(3),
+                ..PoolConfig::default()
             },
-            Percentage {
-                name,
-                target,
-                enabled,
-            } => RawFeatureFlag {
-                flag_name: name.to_string(),
-                gate_type: "percentage".to_string(),
-                target: format!("actors/{}", target),
-                enabled: *enabled,
+            PoolConfig {
+                idle_timeout: Some(-1.0),
+                ..PoolConfig::default()
             },
-            Empty => panic!("can not set this value"),
+        ] {
+            let error = Backend::with_config(&with_pool(pool))
+                .map(|_| ())
+                .unwrap_err();
+            assert_eq!(ErrorKind::Config, error.kind(), "{}", error);
         }
     }
 
-    pub fn from_row(row: Row) -> FeatureFlag {
-        let flag_name: String = row.get("flag_name");
-        let gate_type: String = row.get("gate_type");
-        let target: String = row.get("target");
-        let enabled: bool = row.get("enabled");
+    #[test]
+    fn table_and_schema_can_not_be_empty() {
+        let with_table = |table: Option<&str>, schema: Option<&str>| PostgresConfig {
+            table: table.map(String::from),
+            schema: schema.map(String::from),
+            ..PostgresConfig::new("postgres://localhost")
+        };
 
-        match gate_type.as_ref() {
-            "boolean" => FeatureFlag::Boolean {
-                name: flag_name,
-                enabled,
-            },
-            "actor" => FeatureFlag::Actor {
-                name: flag_name,
-                target,
-                enabled,
+        assert!(statements(&with_table(None, None)).is_ok());
+        assert!(statements(&with_table(Some("billing_flags"), Some("billing"))).is_ok());
+        for postgres_config in [with_table(Some(""), None), with_table(None, Some(""))] {
+            let error = statements(&postgres_config).map(|_| ()).unwrap_err();
+            assert_eq!(ErrorKind::Config, error.kind(), "{}", error);
+        }
+    }
+
+    #[test]
+    fn channel_and_trigger_follow_the_table() {
+        let with_table = |table: Option<&str>, schema: Option<&str>| PostgresConfig {
+            table: table.map(String::from),
+            schema: schema.map(String::from),
+            ..PostgresConfig::new("postgres://localhost")
+        };
+
+        let default = with_table(None, None);
+        assert_eq!(CHANGES_CHANNEL, changes_channel(&default).unwrap());
+        assert_eq!(
+            include_str!("../../migrations/postgres_notify/up.sql"),
+            notify_migration(&default).unwrap()
+        );
+
+        let billing = with_table(Some("billing_flags"), Some("billing"));
+        assert_eq!(
+            "billing_billing_flags_changes",
+            changes_channel(&billing).unwrap()
+        );
+        let migration = notify_migration(&billing).unwrap();
+        assert!(migration.contains(r#"CREATE FUNCTION "billing"."billing_flags_notify_change"()"#));
+        assert!(migration.contains("pg_notify('billing_billing_flags_changes', NEW.flag_name)"));
+        assert!(migration.contains(r#"CREATE TRIGGER "billing_flags_changed""#));
+        assert!(migration.contains(r#"ON "billing"."billing_flags""#));
+        assert_eq!(
+            "billing_flags_changes",
+            changes_channel(&with_table(Some("billing_flags"), None)).unwrap()
+        );
+
+        let error = changes_channel(&with_table(Some(&"flags".repeat(12)), None)).unwrap_err();
+        assert_eq!(ErrorKind::Config, error.kind(), "{}", error);
+    }
+
+    #[cfg(not(feature = "postgres-tls"))]
+    #[test]
+    fn tls_needs_the_feature() {
+        for postgres_config in [
+            with_sslmode("postgres://localhost", Some(SslMode::Require)),
+            PostgresConfig::new("postgres://localhost?sslmode=require"),
+            PostgresConfig {
+                sslrootcert: Some(String::from("ca.pem")),
+                ..PostgresConfig::new("postgres://localhost")
             },
-            "group" => FeatureFlag::Group {
-                name: flag_name,
-                target: GroupSet::new(target),
-                enabled,
+        ] {
+            let error = connection(&postgres_config).map(|_| ()).unwrap_err();
+            assert_eq!(ErrorKind::Config, error.kind(), "{}", error);
+        }
+    }
+
+    #[cfg(feature = "postgres-tls")]
+    #[test]
+    fn certificate_files_have_to_be_valid() {
+        let (config, _) = connection(&with_sslmode(
+            "postgres://localhost",
+            Some(SslMode::VerifyFull),
+        ))
+        .unwrap();
+        assert_eq!(UrlSslMode::Require, config.get_ssl_mode());
+
+        for postgres_config in [
+            PostgresConfig {
+                sslrootcert: Some(String::from("/does/not/exist/ca.pem")),
+                ..PostgresConfig::new("postgres://localhost")
             },
-            "percentage" if target.starts_with("time/") => FeatureFlag::Time {
-                name: flag_name,
-                target: parse_float(target, "time/"),
-                enabled,
+            PostgresConfig {
+                sslrootcert: Some(String::from("Cargo.toml")),
+                ..PostgresConfig::new("postgres://localhost")
             },
-            "percentage" if target.starts_with("actors/") => FeatureFlag::Percentage {
-                name: flag_name,
-                target: parse_float(target, "actors/"),
-                enabled,
+            PostgresConfig {
+                sslcert: Some(String::from("client.pem")),
+                ..PostgresConfig::new("postgres://localhost")
             },
-            _ => panic!("this gate is not supported"),
+        ] {
+            let error = connection(&postgres_config).map(|_| ()).unwrap_err();
+            assert_eq!(ErrorKind::Config, error.kind(), "{}", error);
         }
     }
-}
 
-fn parse_float(mut value: String, prefix: &'static str) -> f64 {
-    value
-        .split_off(prefix.len())
-        .parse()
-        .expect("db contains invalid data")
+    /// needs a running postgres with TLS at `POSTGRES_TLS_URL` and the root certificate of its certificate
+    /// at `POSTGRES_TLS_ROOTCERT`, `test.sh postgres-tls` runs it against an existing server
+    #[cfg(feature = "postgres-tls")]
+    #[test]
+    #[ignore]
+    fn connects_with_tls() {
+        let postgres_config = PostgresConfig {
+            sslmode: Some(SslMode::VerifyFull),
+            sslrootcert: std::env::var("POSTGRES_TLS_ROOTCERT").ok(),
+            ..PostgresConfig::new(std::env::var("POSTGRES_TLS_URL").unwrap())
+        };
+
+        let backend = Backend::with_config(&postgres_config).unwrap();
+        let mut conn = backend.create_conn().unwrap();
+        let ssl: bool = conn
+            .query_one(
+                "SELECT ssl FROM pg_stat_ssl WHERE pid = pg_backend_pid()",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+
+        assert!(ssl);
+
+        // the certificate is self-signed, it can not be verified without the root certificate
+        let (config, tls) = connection(&PostgresConfig {
+            sslrootcert: None,
+            ..postgres_config
+        })
+        .unwrap();
+        assert!(config.connect(tls).is_err());
+    }
 }
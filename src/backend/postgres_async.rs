@@ -0,0 +1,264 @@
+use crate::backend::postgres::{
+    changes_channel, connection, connector, spawn_notify_listener, statements, Tls, MAX_SIZE,
+};
+use crate::backend::sql::{group_by_flag, stored_groups, Statements};
+use crate::backend::{AsyncStorageBackend, ChangeCallback, FlagOutput, SetOutput};
+use crate::config::{PoolConfig, PostgresConfig};
+use crate::models::{FeatureFlag, Flag};
+use crate::Error;
+
+use deadpool_postgres::{Hook, HookError, Manager, ManagerConfig, Pool, RecyclingMethod, Runtime};
+use tokio_postgres::types::ToSql;
+
+use std::collections::HashSet;
+
+pub type ConnectionResult = Result<deadpool_postgres::Object, Error>;
+
+/// async postgres backend, uses the same table and rows as the sync postgres backend
+pub struct Backend {
+    pool: Pool,
+    config: postgres::Config,
+    tls: Tls,
+    statements: Statements,
+    channel: String,
+}
+
+impl Backend {
+    pub fn establish(url: &str) -> Result<Backend, Error> {
+        Backend::with_config(&PostgresConfig::new(url))
+    }
+
+    /// creates the backend from the `[postgres]` section, the same as the sync backend
+    pub fn with_config(postgres_config: &PostgresConfig) -> Result<Backend, Error> {
+        let statements = statements(postgres_config)?;
+        let channel = changes_channel(postgres_config)?;
+        let (config, tls) = connection(postgres_config)?;
+        let mut pool_config: tokio_postgres::Config = postgres_config.url.parse()?;
+        pool_config.ssl_mode(config.get_ssl_mode());
+        if let Some(options) = config.get_options() {
+            pool_config.options(options);
+        }
+        if let Some(connection_timeout) = config.get_connect_timeout() {
+            pool_config.connect_timeout(*connection_timeout);
+        }
+
+        let manager = Manager::from_config(
+            pool_config,
+            connector(&tls),
+            ManagerConfig {
+                recycling_method: RecyclingMethod::Fast,
+            },
+        );
+        let pool = pool(manager, &postgres_config.pool)?;
+        Ok(Backend {
+            pool,
+            config,
+            tls,
+            statements,
+            channel,
+        })
+    }
+
+    pub async fn create_conn(&self) -> ConnectionResult {
+        let conn = self.pool.get().await?;
+        Ok(conn)
+    }
+}
+
+/// deadpool has no `min_idle`, connections past the `idle_timeout` or `max_lifetime` are dropped
+/// when they are taken from the pool instead of being recycled
+fn pool(manager: Manager, pool: &PoolConfig) -> Result<Pool, Error> {
+    pool.validate()?;
+
+    let mut builder = Pool::builder(manager)
+        .runtime(Runtime::Tokio1)
+        .max_size(pool.max_size.unwrap_or(MAX_SIZE) as usize);
+    if let Some(connection_timeout) = pool.connection_timeout() {
+        builder = builder
+            .wait_timeout(Some(connection_timeout))
+            .create_timeout(Some(connection_timeout));
+    }
+
+    let (idle_timeout, max_lifetime) = (pool.idle_timeout(), pool.max_lifetime());
+    if idle_timeout.is_some() || max_lifetime.is_some() {
+        builder = builder.pre_recycle(Hook::sync_fn(move |_, metrics| {
+            if max_lifetime.is_some_and(|max_lifetime| metrics.age() > max_lifetime) {
+                return Err(HookError::message("connection reached the max_lifetime"));
+            }
+            if idle_timeout.is_some_and(|idle_timeout| metrics.last_used() > idle_timeout) {
+                return Err(HookError::message("connection reached the idle_timeout"));
+            }
+            Ok(())
+        }));
+    }
+
+    Ok(builder.build()?)
+}
+
+#[async_trait::async_trait]
+impl AsyncStorageBackend for Backend {
+    async fn set(&self, flag: FeatureFlag) -> SetOutput {
+        let mut conn = self.create_conn().await?;
+
+        use FeatureFlag::*;
+
+        if let Group { .. } = flag {
+            let transaction = conn.transaction().await?;
+
+            let mut gates = Vec::new();
+            for gate in flag.split_groups() {
+                let insertable = gate.to_row()?;
+                let args: Vec<&(dyn ToSql + Sync)> = vec![
+                    &insertable.flag_name,
+                    &insertable.gate_type,
+                    &insertable.target,
+                    &insertable.enabled,
+                    &insertable.enabled,
+                ];
+                gates.push(FeatureFlag::from_row(
+                    transaction
+                        .query_one(self.statements.upsert_gate(), &args)
+                        .await?,
+                )?);
+            }
+
+            transaction.commit().await?;
+
+            return stored_groups(&flag, gates);
+        }
+
+        let insertable = flag.to_row()?;
+        let db_result = match flag {
+            Percentage { .. } | Time { .. } => {
+                let result = if insertable.enabled {
+                    let arg_enable1: Vec<&(dyn ToSql + Sync)> = vec![
+                        &insertable.target,
+                        &insertable.enabled,
+                        &insertable.flag_name,
+                        &insertable.gate_type,
+                    ];
+                    conn.query_opt(self.statements.update_percentage_enable(), &arg_enable1)
+                        .await?
+                } else {
+                    let arg_disable1: Vec<&(dyn ToSql + Sync)> = vec![
+                        &insertable.enabled,
+                        &insertable.flag_name,
+                        &insertable.gate_type,
+                    ];
+                    conn.query_opt(self.statements.update_percentage_disable(), &arg_disable1)
+                        .await?
+                };
+
+                if let Some(row) = result {
+                    row
+                } else {
+                    let arg2: Vec<&(dyn ToSql + Sync)> = vec![
+                        &insertable.flag_name,
+                        &insertable.gate_type,
+                        &insertable.target,
+                        &insertable.enabled,
+                    ];
+                    conn.query_one(self.statements.insert_gate(), &arg2).await?
+                }
+            }
+            _ => {
+                let args: Vec<&(dyn ToSql + Sync)> = vec![
+                    &insertable.flag_name,
+                    &insertable.gate_type,
+                    &insertable.target,
+                    &insertable.enabled,
+                    &insertable.enabled,
+                ];
+                conn.query_one(self.statements.upsert_gate(), &args).await?
+            }
+        };
+
+        FeatureFlag::from_row(db_result)
+    }
+
+    async fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        let conn = self.create_conn().await?;
+
+        let rows = conn
+            .query(self.statements.select_flag(), &[&flag_name])
+            .await?;
+
+        Flag::decode(flag_name, rows.into_iter().map(FeatureFlag::from_row))
+    }
+
+    async fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        let conn = self.create_conn().await?;
+
+        conn.execute(self.statements.delete_flag(), &[&flag_name])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        let mut conn = self.create_conn().await?;
+
+        use FeatureFlag::*;
+
+        let transaction = conn.transaction().await?;
+        for gate in gate.split_groups() {
+            let row = gate.to_row()?;
+            match gate {
+                Time { .. } | Percentage { .. } => {
+                    transaction
+                        .execute(
+                            self.statements.delete_gate_by_type(),
+                            &[&row.flag_name, &row.gate_type],
+                        )
+                        .await?
+                }
+                _ => {
+                    transaction
+                        .execute(
+                            self.statements.delete_gate_by_target(),
+                            &[&row.flag_name, &row.gate_type, &row.target],
+                        )
+                        .await?
+                }
+            };
+        }
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<HashSet<String>, Error> {
+        let conn = self.create_conn().await?;
+
+        let names = conn
+            .query(self.statements.select_flag_names(), &[])
+            .await?
+            .into_iter()
+            .map(|row| row.try_get("flag_name"))
+            .collect::<Result<_, _>>()?;
+
+        Ok(names)
+    }
+
+    async fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        let conn = self.create_conn().await?;
+
+        let rows = conn.query(self.statements.select_all_gates(), &[]).await?;
+
+        group_by_flag(rows.into_iter().map(FeatureFlag::from_row))
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "postgres"
+    }
+
+    /// same as the sync backend, the listener runs on its own thread with a sync connection
+    fn subscribe_changes(&self, on_change: ChangeCallback) -> Result<(), Error> {
+        spawn_notify_listener(
+            self.config.clone(),
+            connector(&self.tls),
+            self.channel.clone(),
+            on_change,
+        )
+    }
+}
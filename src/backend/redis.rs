@@ -1,147 +1,333 @@
-use crate::models::{FeatureFlag, RawOptionalFeatureFlag, RawOptionalFeatureFlags};
+use crate::backend::{
+    pool_builder, spawn_listener, ChangeCallback, FlagOutput, SetOutput, StorageBackend,
+};
+use crate::config::{ConfigError, RedisConfig};
+use crate::error::{Field, ValidationError};
+use crate::models::{FeatureFlag, Flag, RawOptionalFeatureFlag, RawOptionalFeatureFlags};
 use crate::Error;
-use std::collections::HashSet;
-use std::sync::Mutex;
-use state::Storage;
 use redis::Commands;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::time::Duration;
 
-pub type DB = ();
-pub type DBConnection = Connection;
-pub type SetOutput = Result<FeatureFlag, Error>;
-pub type GetOutput = Result<FeatureFlag, Error>;
 pub type ConnectionResult = Result<PooledConnection, Error>;
 type PooledConnection = r2d2::PooledConnection<redis::Client>;
 type Pool = r2d2::Pool<redis::Client>;
 
-const NAMESPACE: &str = "fun_with_flags";
-
-lazy_static::lazy_static! {
-    static ref GLOBAL_POOL: Storage<Mutex<Pool>> = Storage::new();
-}
+/// size of the pool when `max_size` is not set
+const MAX_SIZE: u32 = 15;
 
 ///
 /// redis contains a fun_with_flags set field with all keys that are used
 ///
-/// each field is namespaced with fun_with_flags
+/// each field is namespaced with fun_with_flags, or the `namespace` of the `[redis]` section
 /// each field is of type hash (map)
 ///
 ///
-pub struct Backend {}
-
-pub struct Connection {
-    pub config: String
+pub struct Backend {
+    pool: Pool,
+    client: redis::Client,
+    unique_id: String,
+    namespace: String,
 }
 
-impl Connection {
-    pub fn establish(url: &str) -> Result<Connection, Error> {
-        if GLOBAL_POOL.try_get().is_some() {
-            Ok(Connection{config: url.to_string()})
-        } else{ 
-            let manager = redis::Client::open(url)?;
-            let pool = r2d2::Pool::builder().max_size(15).build(manager)?;
-            GLOBAL_POOL.set(Mutex::new(pool));
-            Self::establish(url)
-        }
+impl Backend {
+    pub fn establish(url: &str) -> Result<Backend, Error> {
+        Backend::with_config(&RedisConfig::new(url))
+    }
+
+    /// creates the backend from the `[redis]` section, with the settings of the pool
+    pub fn with_config(redis_config: &RedisConfig) -> Result<Backend, Error> {
+        let namespace = namespace(redis_config)?;
+        let client = redis::Client::open(redis_config.url.as_str())?;
+        let pool = pool_builder(&redis_config.pool, MAX_SIZE)?
+            .connection_customizer(Box::new(QueryTimeout(redis_config.pool.query_timeout())))
+            .build(client.clone())?;
+        Ok(Backend {
+            pool,
+            client,
+            unique_id: unique_id(),
+            namespace,
+        })
+    }
+
+    fn priv_get(&self, mut conn: PooledConnection, flag_name: &str) -> FlagOutput {
+        let map: RawOptionalFeatureFlags = conn.hgetall(flag_key(&self.namespace, flag_name))?;
+
+        to_flag(flag_name, map)
+    }
+
+    pub fn create_conn(&self) -> ConnectionResult {
+        let conn = self.pool.get()?;
+        Ok(conn)
     }
 }
 
-impl Backend {
-    pub fn set(pool: &DBConnection, flag: FeatureFlag) -> SetOutput {
-        let mut conn = Self::create_conn(pool)?;
+impl StorageBackend for Backend {
+    fn set(&self, flag: FeatureFlag) -> SetOutput {
+        let mut conn = self.create_conn()?;
 
-        let (k, v) = flag.to_redis_value();
-        let key = flag_key(&flag);
+        let values = redis_values(&flag)?;
+        let key = flag_key(&self.namespace, flag.name());
 
         let _: () = redis::pipe()
             .atomic()
-            .sadd(NAMESPACE, flag.name())
+            .sadd(&self.namespace, flag.name())
             .ignore()
-            .hset(&key, k, v)
+            .hset_multiple(&key, &values)
             .ignore()
             .query(&mut *conn)?;
 
-        let flag =  Self::priv_get(conn, flag)?;
+        let stored = self.priv_get(conn, flag.name())?;
+
+        Ok(stored.find(&flag))
+    }
 
-        Ok(flag)
+    fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        let conn = self.create_conn()?;
+        self.priv_get(conn, flag_name)
     }
 
-    pub fn get(pool: &DBConnection, flag: FeatureFlag) -> GetOutput {
-        let conn = Self::create_conn(pool)?;
-        Self::priv_get(conn, flag)
+    fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        let mut conn = self.create_conn()?;
+
+        let key = flag_key(&self.namespace, flag_name);
+
+        let _: () = redis::pipe()
+            .atomic()
+            .srem(&self.namespace, flag_name)
+            .ignore()
+            .del(&key)
+            .ignore()
+            .query(&mut *conn)?;
+
+        Ok(())
     }
 
-    fn priv_get(mut conn: PooledConnection, flag: FeatureFlag) -> GetOutput {
-        let mut map: RawOptionalFeatureFlags = conn.hgetall(flag_key(&flag))?;
+    fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        let mut conn = self.create_conn()?;
 
-        Self::post_processing(&flag, &mut map);
+        let fields = redis_fields(&gate)?;
+        let key = flag_key(&self.namespace, gate.name());
 
-        match map.find(&flag) {
-            Some(x) => Ok(x),
-            None => Ok(FeatureFlag::Empty),
+        let (remaining,): (usize,) = redis::pipe()
+            .atomic()
+            .hdel(&key, fields)
+            .ignore()
+            .hlen(&key)
+            .query(&mut *conn)?;
+
+        if remaining == 0 {
+            let _: () = conn.srem(&self.namespace, gate.name())?;
         }
+
+        Ok(())
     }
 
-    pub fn all_flags_names(pool: &DBConnection) -> Result<HashSet<String>, Error> {
-        let mut conn = Self::create_conn(pool)?;
+    fn list(&self) -> Result<HashSet<String>, Error> {
+        let mut conn = self.create_conn()?;
 
-        let set = conn.smembers(NAMESPACE)?;
+        let set = conn.smembers(&self.namespace)?;
         Ok(set)
     }
 
-    pub fn clean_all(pool: &DBConnection) -> Result<(), Error> {
-        let flag_names = Self::all_flags_names(pool)?;
+    fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        let mut conn = self.create_conn()?;
+
+        let mut flag_names: Vec<String> = conn.smembers(&self.namespace)?;
+        flag_names.sort();
 
-        for flag_name in flag_names {
-            Self::clean(pool, &flag_name)?
+        let mut pipe = redis::pipe();
+        for flag_name in flag_names.iter() {
+            pipe.hgetall(flag_key(&self.namespace, flag_name));
         }
+        let maps: Vec<RawOptionalFeatureFlags> = pipe.query(&mut *conn)?;
 
-        Ok(())
+        flag_names
+            .iter()
+            .zip(maps)
+            .map(|(flag_name, map)| to_flag(flag_name, map))
+            .collect()
     }
 
-    pub fn clean(pool: &DBConnection, flag_name: &str) -> Result<(), Error> {
-        let mut conn = Self::create_conn(pool)?;
+    fn backend_name(&self) -> &'static str {
+        "redis"
+    }
 
-        let key = flag_key_from_str(flag_name);
+    fn publish_change(&self, flag_name: &str) -> Result<(), Error> {
+        let mut conn = self.create_conn()?;
 
-        let _: () = redis::pipe()
-            .atomic()
-            .srem(NAMESPACE, flag_name)
-            .ignore()
-            .del(&key)
-            .ignore()
-            .query(&mut *conn)?;
+        let _: () = conn.publish(
+            changes_channel(&self.namespace),
+            change_payload(&self.unique_id, flag_name),
+        )?;
 
         Ok(())
     }
 
-    fn post_processing(original_flag: &FeatureFlag, output: &mut RawOptionalFeatureFlags) {
-        output.set_flag_name(original_flag.name().to_string());
-        output.update_flag_name();
+    fn subscribe_changes(&self, on_change: ChangeCallback) -> Result<(), Error> {
+        spawn_subscriber(
+            self.client.clone(),
+            changes_channel(&self.namespace),
+            self.unique_id.clone(),
+            on_change,
+        )
     }
+}
 
-    pub fn backend_name() -> &'static str {
-        "redis"
+/// random id of this process, so it can skip its own change notifications
+pub(crate) fn unique_id() -> String {
+    use rand::distributions::Alphanumeric;
+    use rand::{thread_rng, Rng};
+
+    thread_rng().sample_iter(&Alphanumeric).take(16).collect()
+}
+
+/// sets the read and write timeout of the connections of the pool, so a slow redis fails the operation
+#[derive(Debug)]
+struct QueryTimeout(Option<Duration>);
+
+impl r2d2::CustomizeConnection<redis::Connection, redis::RedisError> for QueryTimeout {
+    fn on_acquire(&self, conn: &mut redis::Connection) -> Result<(), redis::RedisError> {
+        conn.set_read_timeout(self.0)?;
+        conn.set_write_timeout(self.0)
     }
+}
 
-    pub fn create_conn(config: &DBConnection) -> ConnectionResult {
-        if let Some(pool) = GLOBAL_POOL.try_get() {
-            let locked_pool = pool.lock().unwrap();
-            let cloned_pool = locked_pool.clone();
-            let conn = cloned_pool.get()?;
-            Ok(conn)
-        } else{ 
-            DBConnection::establish(&config.config)?;
-            Self::create_conn(config)
+/// the payload is `<unique id>:<flag name>`, the same as the elixir library
+pub(crate) fn change_payload(unique_id: &str, flag_name: &str) -> String {
+    format!("{}:{}", unique_id, flag_name)
+}
+
+/// the changed flag name, `None` if the notification came from this process
+pub(crate) fn parse_change<'a>(payload: &'a str, unique_id: &str) -> Option<&'a str> {
+    let (sender, flag_name) = payload.split_once(':')?;
+
+    if sender == unique_id {
+        None
+    } else {
+        Some(flag_name)
+    }
+}
+
+/// listens on the changes channel in a background thread, reconnects when the connection drops
+pub(crate) fn spawn_subscriber(
+    client: redis::Client,
+    channel: String,
+    unique_id: String,
+    on_change: ChangeCallback,
+) -> Result<(), Error> {
+    spawn_listener(on_change, move |on_change| {
+        listen(&client, &channel, &unique_id, on_change)
+    })
+}
+
+/// returns `Ok` when `on_change` asked to stop listening
+fn listen(
+    client: &redis::Client,
+    channel: &str,
+    unique_id: &str,
+    on_change: &ChangeCallback,
+) -> Result<(), Error> {
+    let mut conn = client.get_connection()?;
+    let mut pubsub = conn.as_pubsub();
+    pubsub.subscribe(channel)?;
+
+    if !on_change(None) {
+        return Ok(());
+    }
+
+    loop {
+        let payload: String = pubsub.get_message()?.get_payload()?;
+
+        if let Some(flag_name) = parse_change(&payload, unique_id) {
+            if !on_change(Some(flag_name)) {
+                return Ok(());
+            }
         }
     }
 }
 
-fn flag_key(flag: &FeatureFlag) -> String {
-    flag_key_from_str(flag.name())
+/// converts the hash of the flag to a `Flag` with all its gates, see `Flag::decode`
+pub(crate) fn to_flag(flag_name: &str, mut map: RawOptionalFeatureFlags) -> FlagOutput {
+    map.set_flag_name(flag_name.to_string());
+    map.update_flag_name();
+
+    Flag::try_from(map)
 }
 
-fn flag_key_from_str(flag_name: &str) -> String {
-    format!("{}:{}", NAMESPACE, flag_name)
+/// the fields and values of the gate, fails if a name would break the key or the field it is stored in
+///
+/// The flag name is part of the `{namespace}:{name}` key and the actor ids and group names are split
+/// from the `actor/` and `group/` prefix of the field.
+pub(crate) fn redis_values(flag: &FeatureFlag) -> Result<Vec<(String, String)>, Error> {
+    use FeatureFlag::*;
+
+    check_characters(Field::FlagName, flag.name(), &[':', '/'])?;
+    match flag {
+        Actor { target, .. } => check_characters(Field::ActorId, target, &['/'])?,
+        Group { target, .. } => target
+            .iter()
+            .try_for_each(|group| check_characters(Field::GroupName, group, &['/']))?,
+        _ => (),
+    }
+
+    let values = flag.to_redis_values();
+
+    if values.is_empty() {
+        return Err(ValidationError::NoGate {
+            flag: flag.name().to_string(),
+        }
+        .into());
+    }
+
+    Ok(values)
+}
+
+/// fails if the value contains one of the separators
+fn check_characters(field: Field, value: &str, forbidden: &[char]) -> Result<(), Error> {
+    match value
+        .chars()
+        .find(|character| forbidden.contains(character))
+    {
+        Some(character) => Err(ValidationError::InvalidCharacter {
+            field,
+            value: value.to_string(),
+            character,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn redis_fields(flag: &FeatureFlag) -> Result<Vec<String>, Error> {
+    Ok(redis_values(flag)?
+        .into_iter()
+        .map(|(field, _value)| field)
+        .collect())
+}
+
+/// the namespace of the section, fails if it is empty or would make a set that is also a flag key
+pub(crate) fn namespace(redis_config: &RedisConfig) -> Result<String, Error> {
+    let namespace = redis_config.namespace();
+    if namespace.is_empty() || namespace.contains(':') {
+        return Err(Error::Config(ConfigError::Message(format!(
+            "redis namespace can not be empty or contain ':', got {:?}",
+            namespace
+        ))));
+    }
+
+    Ok(namespace.to_string())
+}
+
+/// the channel the changes of the namespace are sent on,
+/// `fun_with_flags_changes` for the default namespace, the same as the elixir library
+pub(crate) fn changes_channel(namespace: &str) -> String {
+    format!("{}_changes", namespace)
+}
+
+pub(crate) fn flag_key(namespace: &str, flag_name: &str) -> String {
+    format!("{}:{}", namespace, flag_name)
 }
 
 impl redis::FromRedisValue for RawOptionalFeatureFlags {
@@ -152,61 +338,12 @@ impl redis::FromRedisValue for RawOptionalFeatureFlags {
             Value::Bulk(ref items) => {
                 let mut iter = items.iter();
                 let mut feature_flags = RawOptionalFeatureFlags::default();
-                while let (Some(k), Some(v)) = (iter.next(), iter.next()) {
-                    let key: String = from_redis_value(k)?;
-                    let value: String = from_redis_value(v)?;
-
-                    let feature_flag = match key {
-                        x if x.starts_with("actor") => {
-                            let target = x.rsplit("/").next().unwrap();
-                            let enabled: bool = value.parse().unwrap();
-
-                            RawOptionalFeatureFlag {
-                                target: target.to_string(),
-                                gate_type: "actor".to_string(),
-                                enabled,
-                                flag_name: None,
-                            }
-                        }
-
-                        x if x == "percentage" => {
-                            let mut fields = value.split("/");
-                            let gate_type = fields.next().unwrap().to_string();
-                            let target = fields.next().unwrap().to_string();
-                            RawOptionalFeatureFlag {
-                                target,
-                                gate_type,
-                                enabled: true,
-                                flag_name: None,
-                            }
-                        }
-
-                        x if x == "boolean" => {
-                            let enabled: bool = value.parse().unwrap();
-                            RawOptionalFeatureFlag {
-                                gate_type: "boolean".to_string(),
-                                target: String::new(),
-                                enabled,
-                                flag_name: None,
-                            }
-                        }
-
-                        x if x.starts_with("group") => {
-                            let target = x.rsplit("/").next().unwrap();
-                            let enabled: bool = value.parse().unwrap();
-
-                            RawOptionalFeatureFlag {
-                                target: target.to_string(),
-                                gate_type: "group".to_string(),
-                                enabled,
-                                flag_name: None,
-                            }
-                        }
-
-                        _ => unimplemented!(),
-                    };
-
-                    feature_flags.add(feature_flag);
+                while let (Some(field), Some(value)) = (iter.next(), iter.next()) {
+                    feature_flags.add(RawOptionalFeatureFlag {
+                        flag_name: None,
+                        field: from_redis_value(field)?,
+                        value: from_redis_value(value)?,
+                    });
                 }
                 Ok(feature_flags)
             }
@@ -219,24 +356,89 @@ impl redis::FromRedisValue for RawOptionalFeatureFlags {
 }
 
 impl FeatureFlag {
-    pub fn to_redis_value(&self) -> (String, String) {
+    /// the hash fields and values of the gate, a group gate gets one field per group
+    pub fn to_redis_values(&self) -> Vec<(String, String)> {
         use FeatureFlag::*;
 
-        let x = match self {
-            Boolean { enabled, .. } => ("boolean".to_string(), enabled.to_string()),
+        match self {
+            Boolean { enabled, .. } => vec![("boolean".to_string(), enabled.to_string())],
             Actor {
                 target, enabled, ..
-            } => (format!("actor/{}", target), enabled.to_string()),
+            } => vec![(format!("actor/{}", target), enabled.to_string())],
             Group {
                 target, enabled, ..
-            } => (
-                format!("group/{}", target.get_first_unsafe()),
-                enabled.to_string(),
-            ),
-            Time { target, .. } => ("percentage".to_string(), format!("time/{}", target)),
-            Percentage { target, .. } => ("percentage".to_string(), format!("actors/{}", target)),
-            Empty => panic!("can not set this value"),
+            } => target
+                .iter()
+                .map(|group| (format!("group/{}", group), enabled.to_string()))
+                .collect(),
+            Time { target, .. } => vec![("percentage".to_string(), format!("time/{}", target))],
+            Percentage { target, .. } => {
+                vec![("percentage".to_string(), format!("actors/{}", target))]
+            }
+            Empty => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{change_payload, changes_channel, flag_key, namespace, parse_change, redis_values};
+    use crate::backend::CHANGES_CHANNEL;
+    use crate::config::RedisConfig;
+    use crate::models::GroupSet;
+    use crate::{ErrorKind, FeatureFlag};
+
+    #[test]
+    fn parse_change_test() {
+        let payload = change_payload("other", "my:flag");
+
+        assert_eq!(Some("my:flag"), parse_change(&payload, "mine"));
+        assert_eq!(None, parse_change(&payload, "other"));
+        assert_eq!(None, parse_change("no_separator", "mine"));
+    }
+
+    #[test]
+    fn names_that_break_the_encoding_are_rejected() {
+        let actor = |name: &str, target: &str| FeatureFlag::Actor {
+            name: name.to_string(),
+            target: target.to_string(),
+            enabled: true,
+        };
+
+        assert!(redis_values(&actor("testing", "user-1")).is_ok());
+        for flag in [
+            actor("my:flag", "user-1"),
+            actor("my/flag", "user-1"),
+            actor("testing", "users/1"),
+            FeatureFlag::Group {
+                name: String::from("testing"),
+                target: GroupSet::new(String::from("admins/eu")),
+                enabled: true,
+            },
+            FeatureFlag::Empty,
+        ] {
+            let error = redis_values(&flag).unwrap_err();
+            assert_eq!(ErrorKind::Validation, error.kind(), "{}", error);
+        }
+    }
+
+    #[test]
+    fn namespace_prefixes_the_keys() {
+        let with_namespace = |namespace: &str| RedisConfig {
+            namespace: Some(namespace.to_string()),
+            ..RedisConfig::new("redis://localhost")
         };
-        x
+
+        let default = namespace(&RedisConfig::new("redis://localhost")).unwrap();
+        assert_eq!("fun_with_flags:testing", flag_key(&default, "testing"));
+        assert_eq!(CHANGES_CHANNEL, changes_channel(&default));
+        let billing = namespace(&with_namespace("billing")).unwrap();
+        assert_eq!("billing:testing", flag_key(&billing, "testing"));
+        assert_eq!("billing_changes", changes_channel(&billing));
+
+        for invalid in ["", "fun_with_flags:billing"] {
+            let error = namespace(&with_namespace(invalid)).unwrap_err();
+            assert_eq!(ErrorKind::Config, error.kind(), "{}", error);
+        }
     }
 }
@@ -0,0 +1,207 @@
+use crate::backend::redis::{
+    change_payload, changes_channel, flag_key, namespace, redis_fields, redis_values,
+    spawn_subscriber, to_flag, unique_id,
+};
+use crate::backend::{AsyncStorageBackend, ChangeCallback, FlagOutput, SetOutput};
+use crate::config::RedisConfig;
+use crate::models::{FeatureFlag, Flag, RawOptionalFeatureFlags};
+use crate::Error;
+
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::Duration;
+
+/// async redis backend, uses the same keys and hashes as the sync redis backend
+pub struct Backend {
+    conn: ConnectionManager,
+    client: redis::Client,
+    unique_id: String,
+    query_timeout: Option<Duration>,
+    namespace: String,
+}
+
+impl Backend {
+    pub async fn establish(url: &str) -> Result<Backend, Error> {
+        Backend::with_config(&RedisConfig::new(url)).await
+    }
+
+    /// creates the backend from the `[redis]` section, only `connection_timeout` and `query_timeout` apply
+    /// to the multiplexed connection
+    pub async fn with_config(redis_config: &RedisConfig) -> Result<Backend, Error> {
+        redis_config.pool.validate()?;
+        let namespace = namespace(redis_config)?;
+
+        let client = redis::Client::open(redis_config.url.as_str())?;
+        let conn = within(
+            redis_config.pool.connection_timeout(),
+            ConnectionManager::new(client.clone()),
+        )
+        .await?;
+        Ok(Backend {
+            conn,
+            client,
+            unique_id: unique_id(),
+            query_timeout: redis_config.pool.query_timeout(),
+            namespace,
+        })
+    }
+
+    /// runs the query, fails when it takes longer than the query timeout
+    async fn query<T, F: Future<Output = redis::RedisResult<T>>>(
+        &self,
+        query: F,
+    ) -> Result<T, Error> {
+        within(self.query_timeout, query).await
+    }
+
+    /// the connection manager reconnects by itself and is cheap to clone
+    pub fn create_conn(&self) -> ConnectionManager {
+        self.conn.clone()
+    }
+}
+
+/// fails with `Error::Connection` when redis does not answer in time
+async fn within<T, F: Future<Output = redis::RedisResult<T>>>(
+    timeout: Option<Duration>,
+    future: F,
+) -> Result<T, Error> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future).await.map_err(|_| {
+            Error::Connection(format!("redis did not answer within {:?}", timeout).into())
+        })?,
+        None => future.await,
+    }
+    .map_err(Error::from)
+}
+
+#[async_trait::async_trait]
+impl AsyncStorageBackend for Backend {
+    async fn set(&self, flag: FeatureFlag) -> SetOutput {
+        let mut conn = self.create_conn();
+
+        let values = redis_values(&flag)?;
+        let key = flag_key(&self.namespace, flag.name());
+
+        let _: () = self
+            .query(
+                redis::pipe()
+                    .atomic()
+                    .sadd(&self.namespace, flag.name())
+                    .ignore()
+                    .hset_multiple(&key, &values)
+                    .ignore()
+                    .query_async(&mut conn),
+            )
+            .await?;
+
+        self.get(flag).await
+    }
+
+    async fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        let mut conn = self.create_conn();
+
+        let map: RawOptionalFeatureFlags = self
+            .query(conn.hgetall(flag_key(&self.namespace, flag_name)))
+            .await?;
+
+        to_flag(flag_name, map)
+    }
+
+    async fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        let mut conn = self.create_conn();
+
+        let key = flag_key(&self.namespace, flag_name);
+
+        let _: () = self
+            .query(
+                redis::pipe()
+                    .atomic()
+                    .srem(&self.namespace, flag_name)
+                    .ignore()
+                    .del(&key)
+                    .ignore()
+                    .query_async(&mut conn),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        let mut conn = self.create_conn();
+
+        let fields = redis_fields(&gate)?;
+        let key = flag_key(&self.namespace, gate.name());
+
+        let (remaining,): (usize,) = self
+            .query(
+                redis::pipe()
+                    .atomic()
+                    .hdel(&key, fields)
+                    .ignore()
+                    .hlen(&key)
+                    .query_async(&mut conn),
+            )
+            .await?;
+
+        if remaining == 0 {
+            let _: () = self.query(conn.srem(&self.namespace, gate.name())).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<HashSet<String>, Error> {
+        let mut conn = self.create_conn();
+
+        let set = self.query(conn.smembers(&self.namespace)).await?;
+        Ok(set)
+    }
+
+    async fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        let mut conn = self.create_conn();
+
+        let mut flag_names: Vec<String> = self.query(conn.smembers(&self.namespace)).await?;
+        flag_names.sort();
+
+        let mut pipe = redis::pipe();
+        for flag_name in flag_names.iter() {
+            pipe.hgetall(flag_key(&self.namespace, flag_name));
+        }
+        let maps: Vec<RawOptionalFeatureFlags> = self.query(pipe.query_async(&mut conn)).await?;
+
+        flag_names
+            .iter()
+            .zip(maps)
+            .map(|(flag_name, map)| to_flag(flag_name, map))
+            .collect()
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn publish_change(&self, flag_name: &str) -> Result<(), Error> {
+        let mut conn = self.create_conn();
+
+        let _: () = self
+            .query(conn.publish(
+                changes_channel(&self.namespace),
+                change_payload(&self.unique_id, flag_name),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    fn subscribe_changes(&self, on_change: ChangeCallback) -> Result<(), Error> {
+        spawn_subscriber(
+            self.client.clone(),
+            changes_channel(&self.namespace),
+            self.unique_id.clone(),
+            on_change,
+        )
+    }
+}
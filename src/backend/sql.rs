@@ -0,0 +1,333 @@
+//! Statements and row mapping that are shared by the sql backends.
+//!
+//! The gates are stored in the `fun_with_flags_toggles` table, one row per gate, the same as the elixir library does.
+//! The postgres backend can use another table, see `PostgresConfig`.
+
+use crate::backend::SetOutput;
+use crate::error::ValidationError;
+use crate::models::{decode_percentage, FeatureFlag, Flag, GroupSet, RawFeatureFlag};
+use crate::Error;
+
+use std::convert::TryFrom;
+
+/// the struct with one statement per field and a `&str` getter for each, the clients take any `ToStatement`
+/// so a `&String` would not be accepted
+macro_rules! statements {
+    ($($statement:ident),* $(,)?) => {
+        /// statements with `$n` parameters and `RETURNING`, which both Postgres and SQLite understand
+        #[cfg(any(feature = "postgres-backend", feature = "sqlite-backend"))]
+        #[derive(Debug, Clone)]
+        pub(crate) struct Statements {
+            $($statement: String,)*
+        }
+
+        #[cfg(any(feature = "postgres-backend", feature = "sqlite-backend"))]
+        impl Statements {
+            $(
+                pub(crate) fn $statement(&self) -> &str {
+                    &self.$statement
+                }
+            )*
+        }
+    };
+}
+
+statements!(
+    update_percentage_enable,
+    update_percentage_disable,
+    insert_gate,
+    upsert_gate,
+    select_flag,
+    delete_flag,
+    delete_gate_by_type,
+    delete_gate_by_target,
+    select_all_gates,
+    select_flag_names,
+);
+
+#[cfg(any(feature = "postgres-backend", feature = "sqlite-backend"))]
+impl Statements {
+    /// the statements for the table, `schema` and `table` are quoted so any name can be used
+    pub(crate) fn new(schema: Option<&str>, table: &str) -> Statements {
+        let table = qualified(schema, table);
+
+        Statements {
+            update_percentage_enable: format!(
+                r#"UPDATE {table}
+SET "target" = $1, "enabled" = $2
+WHERE {table}."flag_name" = $3 AND {table}."gate_type" = $4
+RETURNING {table}."id", {table}."flag_name", {table}."gate_type", {table}."target", {table}."enabled""#,
+                table = table
+            ),
+            update_percentage_disable: format!(
+                r#"UPDATE {table}
+SET "enabled" = $1
+WHERE {table}."flag_name" = $2 AND {table}."gate_type" = $3
+RETURNING {table}."id", {table}."flag_name", {table}."gate_type", {table}."target", {table}."enabled""#,
+                table = table
+            ),
+            insert_gate: format!(
+                r#"INSERT INTO {table} ("flag_name", "gate_type", "target", "enabled")
+VALUES ($1, $2, $3, $4)
+RETURNING {table}."id", {table}."flag_name", {table}."gate_type", {table}."target", {table}."enabled""#,
+                table = table
+            ),
+            upsert_gate: format!(
+                r#"INSERT INTO {table} ("flag_name", "gate_type", "target", "enabled")
+VALUES ($1, $2, $3, $4) ON CONFLICT ("flag_name", "gate_type", "target")
+DO UPDATE SET "enabled" = $5
+RETURNING {table}."id", {table}."flag_name", {table}."gate_type", {table}."target", {table}."enabled""#,
+                table = table
+            ),
+            select_flag: format!(
+                r#"SELECT {table}."id", {table}."flag_name", {table}."gate_type", {table}."target", {table}."enabled"
+FROM {table} WHERE {table}."flag_name" = $1"#,
+                table = table
+            ),
+            delete_flag: format!(
+                r#"DELETE FROM {table} WHERE {table}."flag_name" = $1"#,
+                table = table
+            ),
+            delete_gate_by_type: format!(
+                r#"DELETE FROM {table}
+WHERE {table}."flag_name" = $1 AND {table}."gate_type" = $2"#,
+                table = table
+            ),
+            delete_gate_by_target: format!(
+                r#"DELETE FROM {table}
+WHERE {table}."flag_name" = $1 AND {table}."gate_type" = $2 AND {table}."target" = $3"#,
+                table = table
+            ),
+            select_all_gates: format!(
+                r#"SELECT {table}."id", {table}."flag_name", {table}."gate_type", {table}."target", {table}."enabled"
+FROM {table} ORDER BY {table}."flag_name""#,
+                table = table
+            ),
+            select_flag_names: format!(
+                r#"SELECT DISTINCT {table}."flag_name" FROM {table}"#,
+                table = table
+            ),
+        }
+    }
+}
+
+/// the `fun_with_flags_toggles` table of the elixir library
+#[cfg(any(feature = "postgres-backend", feature = "sqlite-backend"))]
+impl Default for Statements {
+    fn default() -> Statements {
+        Statements::new(None, TABLE)
+    }
+}
+
+/// the table of the elixir library
+#[cfg(any(feature = "postgres-backend", feature = "sqlite-backend"))]
+pub(crate) const TABLE: &str = "fun_with_flags_toggles";
+
+/// quotes the identifier, a `"` in it is doubled
+#[cfg(any(feature = "postgres-backend", feature = "sqlite-backend"))]
+pub(crate) fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// the quoted name, prefixed with the quoted schema when there is one
+#[cfg(any(feature = "postgres-backend", feature = "sqlite-backend"))]
+pub(crate) fn qualified(schema: Option<&str>, name: &str) -> String {
+    match schema {
+        Some(schema) => format!("{}.{}", quote(schema), quote(name)),
+        None => quote(name),
+    }
+}
+
+pub(crate) fn stored_groups(flag: &FeatureFlag, gates: Vec<FeatureFlag>) -> SetOutput {
+    match Flag::new(flag.name(), gates).find(flag) {
+        FeatureFlag::Empty => Err(ValidationError::NoGate {
+            flag: flag.name().to_string(),
+        }
+        .into()),
+        stored => Ok(stored),
+    }
+}
+
+/// groups gates that are sorted by flag name into flags, see `Flag::decode` for the gates that can not be decoded
+pub(crate) fn group_by_flag<I>(gates: I) -> Result<Vec<Flag>, Error>
+where
+    I: IntoIterator<Item = Result<FeatureFlag, Error>>,
+{
+    let mut flags: Vec<Flag> = Vec::new();
+
+    for gate in gates {
+        let name = match &gate {
+            Ok(gate) => gate.name().to_string(),
+            Err(Error::Decode { flag, .. }) => flag.clone(),
+            Err(_) => String::new(),
+        };
+
+        match flags.last_mut() {
+            Some(flag) if flag.name == name => flag.push_decoded(gate)?,
+            _ => flags.push(Flag::decode(name, vec![gate])?),
+        }
+    }
+
+    Ok(flags)
+}
+
+impl FeatureFlag {
+    /// the row that stores the gate, fails for `FeatureFlag::Empty`
+    pub fn to_row(&self) -> Result<RawFeatureFlag, Error> {
+        use FeatureFlag::*;
+
+        let row = match self {
+            Boolean { name, enabled } => RawFeatureFlag {
+                flag_name: name.to_string(),
+                gate_type: "boolean".to_string(),
+                target: "_fwf_none".to_string(),
+                enabled: *enabled,
+            },
+            Actor {
+                name,
+                target,
+                enabled,
+            } => RawFeatureFlag {
+                flag_name: name.to_string(),
+                gate_type: "actor".to_string(),
+                target: target.to_string(),
+                enabled: *enabled,
+            },
+            Group {
+                name,
+                target,
+                enabled,
+            } => RawFeatureFlag {
+                flag_name: name.to_string(),
+                gate_type: "group".to_string(),
+                target: target.to_optional_string().unwrap_or("").to_string(),
+                enabled: *enabled,
+            },
+            Time {
+                name,
+                target,
+                enabled,
+            } => RawFeatureFlag {
+                flag_name: name.to_string(),
+                gate_type: "percentage".to_string(),
+                target: format!("time/{}", target),
+                enabled: *enabled,
+            },
+            Percentage {
+                name,
+                target,
+                enabled,
+            } => RawFeatureFlag {
+                flag_name: name.to_string(),
+                gate_type: "percentage".to_string(),
+                target: format!("actors/{}", target),
+                enabled: *enabled,
+            },
+            Empty => {
+                return Err(ValidationError::NoGate {
+                    flag: String::new(),
+                }
+                .into())
+            }
+        };
+
+        Ok(row)
+    }
+}
+
+impl TryFrom<RawFeatureFlag> for FeatureFlag {
+    type Error = Error;
+
+    /// the gate that is stored in the row
+    fn try_from(raw: RawFeatureFlag) -> Result<FeatureFlag, Error> {
+        let RawFeatureFlag {
+            flag_name,
+            gate_type,
+            target,
+            enabled,
+        } = raw;
+
+        let decoded = match gate_type.as_ref() {
+            "boolean" => Some(FeatureFlag::Boolean {
+                name: flag_name.clone(),
+                enabled,
+            }),
+            "actor" => Some(FeatureFlag::Actor {
+                name: flag_name.clone(),
+                target: target.clone(),
+                enabled,
+            }),
+            "group" => Some(FeatureFlag::Group {
+                name: flag_name.clone(),
+                target: GroupSet::new(target.clone()),
+                enabled,
+            }),
+            "percentage" => decode_percentage(&flag_name, &target, enabled),
+            _ => None,
+        };
+
+        decoded.ok_or(Error::Decode {
+            flag: flag_name,
+            gate_type,
+            raw: target,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::group_by_flag;
+    #[cfg(any(feature = "postgres-backend", feature = "sqlite-backend"))]
+    use super::Statements;
+    use crate::models::{FeatureFlag, RawFeatureFlag};
+
+    use std::convert::TryFrom;
+
+    fn row(flag_name: &str, gate_type: &str, target: &str) -> RawFeatureFlag {
+        RawFeatureFlag {
+            flag_name: flag_name.to_string(),
+            gate_type: gate_type.to_string(),
+            target: target.to_string(),
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn undecodable_rows_do_not_fail_the_other_flags() {
+        let rows = vec![
+            row("a", "boolean", "_fwf_none"),
+            row("a", "percentage", "time/not-a-number"),
+            row("b", "timezone", "Europe/Amsterdam"),
+            row("c", "percentage", "actors/0.5"),
+        ];
+
+        let flags = group_by_flag(rows.into_iter().map(FeatureFlag::try_from)).unwrap();
+
+        assert_eq!(3, flags.len());
+        assert_eq!(1, flags[0].gates.len());
+        assert_eq!(1, flags[0].invalid_gates.len());
+        assert!(flags[1].gates.is_empty());
+        assert_eq!("timezone", flags[1].invalid_gates[0].gate_type);
+        assert_eq!(
+            vec![FeatureFlag::Percentage {
+                name: String::from("c"),
+                target: 0.5,
+                enabled: true,
+            }],
+            flags[2].gates
+        );
+    }
+
+    #[cfg(any(feature = "postgres-backend", feature = "sqlite-backend"))]
+    #[test]
+    fn statements_use_the_quoted_table() {
+        assert_eq!(
+            r#"DELETE FROM "fun_with_flags_toggles" WHERE "fun_with_flags_toggles"."flag_name" = $1"#,
+            Statements::default().delete_flag()
+        );
+        assert_eq!(
+            r#"SELECT DISTINCT "billing"."my""flags"."flag_name" FROM "billing"."my""flags""#,
+            Statements::new(Some("billing"), r#"my"flags"#).select_flag_names()
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use crate::backend::sql::{group_by_flag, stored_groups, Statements};
+use crate::backend::{FlagOutput, SetOutput, StorageBackend};
+use crate::models::{FeatureFlag, Flag, RawFeatureFlag};
+use crate::Error;
+
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, OptionalExtension, Row};
+
+use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub type ConnectionResult = Result<r2d2::PooledConnection<SqliteConnectionManager>, Error>;
+type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+/// sqlite backend, uses the same table and statements as the postgres backend
+///
+/// The table is created by `migrations/sqlite/up.sql`.
+/// The async backend runs the calls on the blocking threads of tokio, rusqlite has no async client.
+#[derive(Clone)]
+pub struct Backend {
+    pool: Pool,
+    statements: Arc<Statements>,
+}
+
+impl Backend {
+    /// `url` is `sqlite://<path>`, the connections of the pool share the database file
+    pub fn establish(url: &str) -> Result<Backend, Error> {
+        let path = url.strip_prefix("sqlite://").unwrap_or(url);
+
+        let manager = SqliteConnectionManager::file(path)
+            .with_init(|conn| conn.busy_timeout(Duration::from_secs(5)));
+        let pool = r2d2::Pool::new(manager)?;
+        Ok(Backend {
+            pool,
+            statements: Arc::new(Statements::default()),
+        })
+    }
+
+    pub fn create_conn(&self) -> ConnectionResult {
+        let conn = self.pool.get()?;
+        Ok(conn)
+    }
+}
+
+impl StorageBackend for Backend {
+    fn set(&self, flag: FeatureFlag) -> SetOutput {
+        let mut conn = self.create_conn()?;
+
+        use FeatureFlag::*;
+
+        if let Group { .. } = flag {
+            return set_groups(&mut conn, &self.statements, &flag);
+        }
+
+        let insertable = flag.to_row()?;
+        let stored = match flag {
+            Percentage { .. } | Time { .. } => {
+                let result = if insertable.enabled {
+                    conn.query_row(
+                        self.statements.update_percentage_enable(),
+                        params![
+                            insertable.target,
+                            insertable.enabled,
+                            insertable.flag_name,
+                            insertable.gate_type
+                        ],
+                        raw_from_row,
+                    )
+                    .optional()?
+                } else {
+                    conn.query_row(
+                        self.statements.update_percentage_disable(),
+                        params![
+                            insertable.enabled,
+                            insertable.flag_name,
+                            insertable.gate_type
+                        ],
+                        raw_from_row,
+                    )
+                    .optional()?
+                };
+
+                match result {
+                    Some(stored) => stored,
+                    None => conn.query_row(
+                        self.statements.insert_gate(),
+                        params![
+                            insertable.flag_name,
+                            insertable.gate_type,
+                            insertable.target,
+                            insertable.enabled
+                        ],
+                        raw_from_row,
+                    )?,
+                }
+            }
+            _ => conn.query_row(
+                self.statements.upsert_gate(),
+                params![
+                    insertable.flag_name,
+                    insertable.gate_type,
+                    insertable.target,
+                    insertable.enabled,
+                    insertable.enabled
+                ],
+                raw_from_row,
+            )?,
+        };
+
+        FeatureFlag::try_from(stored)
+    }
+
+    fn get_flag(&self, flag_name: &str) -> FlagOutput {
+        let conn = self.create_conn()?;
+
+        let mut statement = conn.prepare(self.statements.select_flag())?;
+        let rows = statement
+            .query_map([flag_name], raw_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Flag::decode(flag_name, rows.into_iter().map(FeatureFlag::try_from))
+    }
+
+    fn clear(&self, flag_name: &str) -> Result<(), Error> {
+        let conn = self.create_conn()?;
+
+        conn.execute(self.statements.delete_flag(), [flag_name])?;
+
+        Ok(())
+    }
+
+    fn clear_gate(&self, gate: FeatureFlag) -> Result<(), Error> {
+        let mut conn = self.create_conn()?;
+
+        use FeatureFlag::*;
+
+        let transaction = conn.transaction()?;
+        for gate in gate.split_groups() {
+            let row = gate.to_row()?;
+            match gate {
+                Time { .. } | Percentage { .. } => transaction.execute(
+                    self.statements.delete_gate_by_type(),
+                    params![row.flag_name, row.gate_type],
+                )?,
+                _ => transaction.execute(
+                    self.statements.delete_gate_by_target(),
+                    params![row.flag_name, row.gate_type, row.target],
+                )?,
+            };
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn list(&self) -> Result<HashSet<String>, Error> {
+        let conn = self.create_conn()?;
+
+        let mut statement = conn.prepare(self.statements.select_flag_names())?;
+        let names = statement
+            .query_map([], |row| row.get("flag_name"))?
+            .collect::<Result<HashSet<String>, _>>()?;
+
+        Ok(names)
+    }
+
+    fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        let conn = self.create_conn()?;
+
+        let mut statement = conn.prepare(self.statements.select_all_gates())?;
+        let rows = statement
+            .query_map([], raw_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        group_by_flag(rows.into_iter().map(FeatureFlag::try_from))
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "sqlite"
+    }
+}
+
+#[cfg(feature = "async")]
+blocking_async_backend!(Backend);
+
+/// upserts one row per group in a single transaction
+fn set_groups(
+    conn: &mut rusqlite::Connection,
+    statements: &Statements,
+    flag: &FeatureFlag,
+) -> SetOutput {
+    let transaction = conn.transaction()?;
+
+    let mut gates = Vec::new();
+    for gate in flag.split_groups() {
+        let insertable = gate.to_row()?;
+        gates.push(
+            transaction
+                .query_row(
+                    statements.upsert_gate(),
+                    params![
+                        insertable.flag_name,
+                        insertable.gate_type,
+                        insertable.target,
+                        insertable.enabled,
+                        insertable.enabled
+                    ],
+                    raw_from_row,
+                )?
+                .try_into()?,
+        );
+    }
+
+    transaction.commit()?;
+
+    stored_groups(flag, gates)
+}
+
+/// the row as it is stored, it is decoded with `FeatureFlag::try_from`
+fn raw_from_row(row: &Row) -> rusqlite::Result<RawFeatureFlag> {
+    Ok(RawFeatureFlag {
+        flag_name: row.get("flag_name")?,
+        gate_type: row.get("gate_type")?,
+        target: row.get("target")?,
+        enabled: row.get("enabled")?,
+    })
+}
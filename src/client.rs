@@ -0,0 +1,644 @@
+use crate::backend::{self, StorageBackend};
+use crate::config::{fetch_config, BackendType, CacheConfig, DefaultsConfig, RawConfig};
+use crate::error::ErrorCallback;
+use crate::models::GroupSet;
+use crate::overrides::{Evaluation, Layer, Overrides};
+use crate::{Actor, Error, FeatureFlag, Flag, Group, Output};
+
+use std::collections::HashSet;
+
+/// Client that owns the backend (and with that the pool and cache).
+///
+/// Create it once and pass it around, instead of reading the configuration on every call.
+pub struct FunWithFlags {
+    backend: Box<dyn StorageBackend>,
+    overrides: Overrides,
+    defaults: DefaultsConfig,
+    on_error: Option<ErrorCallback>,
+}
+
+/// Builder for the `FunWithFlags` client.
+///
+/// If no url or config is given, the config is read from `fun-with-flags.toml` and the environment.
+#[derive(Default)]
+pub struct FunWithFlagsBuilder {
+    url: Option<String>,
+    backend_type: Option<BackendType>,
+    config: Option<RawConfig>,
+    cache: Option<CacheConfig>,
+    overrides: Option<Overrides>,
+    defaults: Option<DefaultsConfig>,
+    on_error: Option<ErrorCallback>,
+}
+
+impl FunWithFlagsBuilder {
+    pub fn url<S: Into<String>>(mut self, url: S) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// backend to use with `url`, defaults to `BackendType::Auto`
+    pub fn backend_type(mut self, backend_type: BackendType) -> Self {
+        self.backend_type = Some(backend_type);
+        self
+    }
+
+    pub fn config(mut self, config: RawConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// settings of the cache, overrides the `[cache]` section of the config
+    pub fn cache(mut self, cache: CacheConfig) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// overrides that are checked before the backend, instead of the ones from the `[overrides]` section of the config
+    pub fn overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = Some(overrides);
+        self
+    }
+
+    /// values of the flags when the backend fails, instead of the `[defaults]` section of the config
+    pub fn defaults(mut self, defaults: DefaultsConfig) -> Self {
+        self.defaults = Some(defaults);
+        self
+    }
+
+    /// called when a flag falls back to its default, by default the error is logged as a warning
+    pub fn on_error<F: Fn(&str, &Error) + Send + Sync + 'static>(mut self, on_error: F) -> Self {
+        self.on_error = Some(Box::new(on_error));
+        self
+    }
+
+    pub fn build(self) -> Result<FunWithFlags, Error> {
+        let backend_type = self.backend_type.unwrap_or(BackendType::Auto);
+        let config = match (&self.url, self.config) {
+            (None, None) => Some(fetch_config()?),
+            (_, config) => config,
+        };
+
+        let overrides = match self.overrides {
+            Some(overrides) => overrides,
+            None => Overrides::load(
+                &config
+                    .as_ref()
+                    .map(RawConfig::overrides)
+                    .unwrap_or_default(),
+            )?,
+        };
+
+        let defaults = match self.defaults {
+            Some(defaults) => defaults,
+            None => config.as_ref().map(RawConfig::defaults).unwrap_or_default(),
+        };
+
+        let backend = match (self.url, config) {
+            (Some(url), _) => {
+                backend::establish_with_cache(backend_type, &url, self.cache.unwrap_or_default())?
+            }
+            (None, Some(mut config)) => {
+                if let Some(cache) = self.cache {
+                    config.cache_config = Some(cache);
+                }
+                backend::from_config(&config)?
+            }
+            (None, None) => unreachable!("the config is fetched when there is no url"),
+        };
+
+        Ok(FunWithFlags {
+            backend,
+            overrides,
+            defaults,
+            on_error: self.on_error,
+        })
+    }
+}
+
+impl FunWithFlags {
+    /// creates a client from `fun-with-flags.toml` and the `DATABASE_*` environment variables
+    pub fn new() -> Result<FunWithFlags, Error> {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> FunWithFlagsBuilder {
+        FunWithFlagsBuilder::default()
+    }
+
+    pub fn from_config(config: RawConfig) -> Result<FunWithFlags, Error> {
+        Self::builder().config(config).build()
+    }
+
+    pub fn with_backend<B: StorageBackend + 'static>(backend: B) -> FunWithFlags {
+        FunWithFlags {
+            backend: Box::new(backend),
+            overrides: Overrides::new(),
+            defaults: DefaultsConfig::default(),
+            on_error: None,
+        }
+    }
+
+    /// replaces the overrides that are checked before the backend
+    pub fn with_overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// replaces the values of the flags when the backend fails
+    pub fn with_defaults(mut self, defaults: DefaultsConfig) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    /// replaces the callback for errors of flags that fall back to their default
+    pub fn with_error_callback<F: Fn(&str, &Error) + Send + Sync + 'static>(
+        mut self,
+        on_error: F,
+    ) -> Self {
+        self.on_error = Some(Box::new(on_error));
+        self
+    }
+
+    pub fn backend(&self) -> &dyn StorageBackend {
+        self.backend.as_ref()
+    }
+
+    pub fn overrides(&self) -> &Overrides {
+        &self.overrides
+    }
+
+    pub fn enable(&self, flag: &str) -> Output {
+        self.set(FeatureFlag::Boolean {
+            name: flag.to_string(),
+            enabled: true,
+        })
+    }
+
+    pub fn enable_for<T: Actor>(&self, flag: &str, actor: &T) -> Output {
+        self.set(FeatureFlag::Actor {
+            name: flag.to_string(),
+            target: actor.feature_flag_id(),
+            enabled: true,
+        })
+    }
+
+    pub fn disable(&self, flag: &str) -> Output {
+        self.set(FeatureFlag::Boolean {
+            name: flag.to_string(),
+            enabled: false,
+        })
+    }
+
+    pub fn disable_for<T: Actor>(&self, flag: &str, actor: &T) -> Output {
+        self.set(FeatureFlag::Actor {
+            name: flag.to_string(),
+            target: actor.feature_flag_id(),
+            enabled: false,
+        })
+    }
+
+    /// replaces the settings of the cache at runtime, fails when the cached feature is not enabled
+    pub fn configure_cache(&self, cache: CacheConfig) -> Result<(), Error> {
+        self.backend.configure_cache(cache)
+    }
+
+    /// loads the flag with all its gates, a flag that is not stored has no gates
+    pub fn get_flag(&self, flag: &str) -> Result<Flag, Error> {
+        self.backend.get_flag(flag)
+    }
+
+    /// names of all the stored flags
+    pub fn all_flag_names(&self) -> Result<HashSet<String>, Error> {
+        self.backend.list()
+    }
+
+    /// all the stored flags with all their gates, sorted by name
+    pub fn all_flags(&self) -> Result<Vec<Flag>, Error> {
+        self.backend.all_flags()
+    }
+
+    /// checks the overrides, then the boolean gate and if that is not enabled the percentage of time gate
+    ///
+    /// When the backend fails the flag falls back to its default, use `try_enabled` to get the error.
+    pub fn enabled(&self, flag: &str) -> bool {
+        let default = self.defaults.enabled(flag);
+        self.enabled_or(flag, default)
+    }
+
+    /// like `enabled`, but returns `default` when the backend fails
+    pub fn enabled_or(&self, flag: &str, default: bool) -> bool {
+        match self.evaluate(flag) {
+            Ok(evaluation) => evaluation.enabled,
+            Err(e) => self.fall_back(flag, e, default),
+        }
+    }
+
+    /// like `enabled`, but returns the error when the backend fails
+    pub fn try_enabled(&self, flag: &str) -> Result<bool, Error> {
+        self.evaluate(flag).map(|evaluation| evaluation.enabled)
+    }
+
+    /// like `enabled`, but also tells which layer decided, an override or the backend
+    pub fn evaluate(&self, flag: &str) -> Result<Evaluation, Error> {
+        if let Some(evaluation) = self.overrides.evaluate(flag) {
+            return Ok(evaluation);
+        }
+
+        let flag = self.backend.get_flag(flag)?;
+        flag.check_decoded(self.defaults.decode_policy())?;
+        Ok(Evaluation {
+            enabled: flag.enabled(),
+            layer: Layer::Backend,
+        })
+    }
+
+    pub fn disabled(&self, flag: &str) -> bool {
+        !self.enabled(flag)
+    }
+
+    /// checks the overrides, then the gates in the same order as the elixir library, see `Flag::enabled_for`
+    ///
+    /// When the backend fails the flag falls back to its default, use `try_enabled_for` to get the error.
+    pub fn enabled_for<T: Actor + Group>(&self, flag: &str, actor: &T) -> bool {
+        let default = self.defaults.enabled(flag);
+        self.enabled_for_or(flag, actor, default)
+    }
+
+    /// like `enabled_for`, but returns `default` when the backend fails
+    pub fn enabled_for_or<T: Actor + Group>(&self, flag: &str, actor: &T, default: bool) -> bool {
+        match self.evaluate_for(flag, actor) {
+            Ok(evaluation) => evaluation.enabled,
+            Err(e) => self.fall_back(flag, e, default),
+        }
+    }
+
+    /// like `enabled_for`, but returns the error when the backend fails
+    pub fn try_enabled_for<T: Actor + Group>(&self, flag: &str, actor: &T) -> Result<bool, Error> {
+        self.evaluate_for(flag, actor)
+            .map(|evaluation| evaluation.enabled)
+    }
+
+    /// reports the error of the backend and returns the default
+    fn fall_back(&self, flag: &str, error: Error, default: bool) -> bool {
+        match &self.on_error {
+            Some(on_error) => on_error(flag, &error),
+            None => log::warn!(
+                "flag {} is {} by default, the backend failed: {}",
+                flag,
+                if default { "enabled" } else { "disabled" },
+                error
+            ),
+        }
+
+        default
+    }
+
+    /// like `enabled_for`, but also tells which layer decided, an override or the backend
+    pub fn evaluate_for<T: Actor + Group>(
+        &self,
+        flag: &str,
+        actor: &T,
+    ) -> Result<Evaluation, Error> {
+        if let Some(evaluation) = self.overrides.evaluate_for(flag, actor) {
+            return Ok(evaluation);
+        }
+
+        let flag = self.backend.get_flag(flag)?;
+        flag.check_decoded(self.defaults.decode_policy())?;
+        Ok(Evaluation {
+            enabled: flag.enabled_for(actor),
+            layer: Layer::Backend,
+        })
+    }
+
+    pub fn disabled_for<T: Actor + Group>(&self, flag: &str, actor: &T) -> bool {
+        !self.enabled_for(flag, actor)
+    }
+
+    /// validates the gate before the backend stores it
+    fn set(&self, flag: FeatureFlag) -> Output {
+        flag.validate()?;
+        self.backend.set(flag)
+    }
+
+    /// removes all the gates of the flag
+    pub fn clear(&self, flag: &str) -> Result<(), Error> {
+        self.backend.clear(flag)
+    }
+
+    /// removes the actor gate, the actor falls back to the other gates
+    pub fn clear_for<T: Actor>(&self, flag: &str, actor: &T) -> Result<(), Error> {
+        self.backend.clear_gate(FeatureFlag::Actor {
+            name: flag.to_string(),
+            target: actor.feature_flag_id(),
+            enabled: false,
+        })
+    }
+
+    /// removes the gate of one group
+    pub fn clear_for_group(&self, flag: &str, group_name: &str) -> Result<(), Error> {
+        self.backend.clear_gate(FeatureFlag::Group {
+            name: flag.to_string(),
+            target: GroupSet::new(group_name.to_string()),
+            enabled: false,
+        })
+    }
+
+    /// removes the boolean gate
+    pub fn clear_boolean(&self, flag: &str) -> Result<(), Error> {
+        self.backend.clear_gate(FeatureFlag::Boolean {
+            name: flag.to_string(),
+            enabled: false,
+        })
+    }
+
+    /// removes the percentage gate, both of time and of actors
+    pub fn clear_percentage(&self, flag: &str) -> Result<(), Error> {
+        self.backend.clear_gate(FeatureFlag::Percentage {
+            name: flag.to_string(),
+            target: 0.0,
+            enabled: false,
+        })
+    }
+
+    pub fn enable_percentage_of_time(&self, flag: &str, percentage: f64) -> Output {
+        self.set(FeatureFlag::Time {
+            name: flag.to_string(),
+            target: percentage,
+            enabled: true,
+        })
+    }
+
+    pub fn disable_percentage_of_time(&self, flag: &str) -> Output {
+        self.set(FeatureFlag::Time {
+            name: flag.to_string(),
+            target: 0.0,
+            enabled: false,
+        })
+    }
+
+    pub fn enable_percentage_of_actors(&self, flag: &str, percentage: f64) -> Output {
+        self.set(FeatureFlag::Percentage {
+            name: flag.to_string(),
+            target: percentage,
+            enabled: true,
+        })
+    }
+
+    pub fn disable_percentage_of_actors(&self, flag: &str) -> Output {
+        self.set(FeatureFlag::Percentage {
+            name: flag.to_string(),
+            target: 0.0,
+            enabled: false,
+        })
+    }
+
+    pub fn enable_for_group(&self, flag: &str, group_name: &str) -> Output {
+        self.set(FeatureFlag::Group {
+            name: flag.to_string(),
+            target: GroupSet::new(group_name.to_string()),
+            enabled: true,
+        })
+    }
+
+    pub fn disable_for_group(&self, flag: &str, group_name: &str) -> Output {
+        self.set(FeatureFlag::Group {
+            name: flag.to_string(),
+            target: GroupSet::new(group_name.to_string()),
+            enabled: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::MockStorageBackend;
+    use crate::config::{BackendType, DefaultsConfig, FailurePolicy};
+    use crate::{Error, ErrorKind, Evaluation, FeatureFlag, Flag, FunWithFlags, Layer, Overrides};
+
+    #[test]
+    fn client_enabled_test() {
+        let mut mock = MockStorageBackend::new();
+        mock.expect_get_flag().returning(|name| {
+            Ok(Flag::new(
+                name,
+                vec![FeatureFlag::Boolean {
+                    name: name.to_string(),
+                    enabled: true,
+                }],
+            ))
+        });
+
+        let client = FunWithFlags::with_backend(mock);
+
+        assert!(client.enabled("oke"));
+    }
+
+    #[test]
+    fn client_disabled_actor_overrides_boolean_test() {
+        let mut mock = MockStorageBackend::new();
+        mock.expect_get_flag().returning(|name| {
+            Ok(Flag::new(
+                name,
+                vec![
+                    FeatureFlag::Actor {
+                        name: name.to_string(),
+                        target: "actor".to_string(),
+                        enabled: false,
+                    },
+                    FeatureFlag::Boolean {
+                        name: name.to_string(),
+                        enabled: true,
+                    },
+                ],
+            ))
+        });
+
+        let client = FunWithFlags::with_backend(mock);
+
+        assert!(client.enabled("oke"));
+        assert!(!client.enabled_for("oke", &"actor"));
+    }
+
+    #[test]
+    fn client_override_wins_over_backend_test() {
+        let mut mock = MockStorageBackend::new();
+        mock.expect_get_flag()
+            .withf(|name| name == "stored")
+            .returning(|name| {
+                Ok(Flag::new(
+                    name,
+                    vec![FeatureFlag::Boolean {
+                        name: name.to_string(),
+                        enabled: true,
+                    }],
+                ))
+            });
+
+        let path = std::env::temp_dir().join(format!(
+            "fun-with-flags-{}-overrides.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "[forced]\nboolean = false\nactors = { actor = true }\n",
+        )
+        .unwrap();
+        let overrides = Overrides::new().with_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let client = FunWithFlags::with_backend(mock).with_overrides(overrides);
+
+        assert_eq!(
+            Evaluation {
+                enabled: false,
+                layer: Layer::File
+            },
+            client.evaluate("forced").unwrap()
+        );
+        assert!(client.enabled_for("forced", &"actor"));
+        assert_eq!(Layer::Backend, client.evaluate("stored").unwrap().layer);
+        assert!(client.enabled("stored"));
+    }
+
+    #[test]
+    fn client_failing_backend_uses_defaults_test() {
+        use std::sync::{Arc, Mutex};
+
+        let mut mock = MockStorageBackend::new();
+        mock.expect_get_flag()
+            .returning(|_| Err(Error::Custom(String::from("connection refused"))));
+
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let on_error = reported.clone();
+
+        let defaults = DefaultsConfig {
+            on_error: Some(FailurePolicy::Open),
+            ..DefaultsConfig::default()
+        }
+        .flag("new_checkout", false);
+        let client = FunWithFlags::with_backend(mock)
+            .with_defaults(defaults)
+            .with_error_callback(move |flag, error| {
+                on_error
+                    .lock()
+                    .unwrap()
+                    .push(format!("{}: {}", flag, error))
+            });
+
+        assert!(!client.enabled("new_checkout"));
+        assert!(client.enabled_for("other", &"actor"));
+        assert!(!client.enabled_or("other", false));
+        assert!(client.evaluate("other").is_err());
+        assert_eq!(
+            vec![
+                "new_checkout: connection refused",
+                "other: connection refused",
+                "other: connection refused"
+            ],
+            *reported.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn client_try_enabled_returns_errors_test() {
+        let mut mock = MockStorageBackend::new();
+        mock.expect_get_flag().returning(|name| match name {
+            "stored" => Ok(Flag::new(
+                name,
+                vec![FeatureFlag::Actor {
+                    name: name.to_string(),
+                    target: "actor".to_string(),
+                    enabled: true,
+                }],
+            )),
+            _ => Err(Error::Custom(String::from("connection refused"))),
+        });
+        mock.expect_set()
+            .returning(|_| Err(Error::Custom(String::from("connection refused"))));
+
+        let client = FunWithFlags::with_backend(mock);
+
+        assert!(!client.try_enabled("stored").unwrap());
+        assert!(client.try_enabled_for("stored", &"actor").unwrap());
+        assert!(client.try_enabled("down").is_err());
+        assert!(client.try_enabled_for("down", &"actor").is_err());
+        assert!(client.enable("down").is_err());
+    }
+
+    #[test]
+    fn client_validates_before_writing_test() {
+        let mut mock = MockStorageBackend::new();
+        mock.expect_set().never();
+
+        let client = FunWithFlags::with_backend(mock);
+
+        for result in [
+            client.enable_percentage_of_time("testing", 1.7),
+            client.enable_percentage_of_actors("testing", -0.2),
+            client.enable(""),
+            client.enable_for("testing", &""),
+        ] {
+            assert_eq!(ErrorKind::Validation, result.unwrap_err().kind());
+        }
+    }
+
+    #[test]
+    fn client_decode_policy_test() {
+        use crate::config::DecodePolicy;
+
+        let mut mock = MockStorageBackend::new();
+        mock.expect_get_flag().returning(|name| {
+            Flag::decode(
+                name,
+                vec![
+                    Ok(FeatureFlag::Boolean {
+                        name: name.to_string(),
+                        enabled: true,
+                    }),
+                    Err(Error::Decode {
+                        flag: name.to_string(),
+                        gate_type: String::from("timezone"),
+                        raw: String::from("Europe/Amsterdam"),
+                    }),
+                ],
+            )
+        });
+
+        let client = FunWithFlags::with_backend(mock).with_defaults(DefaultsConfig {
+            on_decode_error: Some(DecodePolicy::Fail),
+            ..DefaultsConfig::default()
+        });
+
+        assert!(!client.enabled("testing"));
+        assert!(client.try_enabled("testing").is_err());
+        assert!(client.get_flag("testing").is_ok());
+
+        let client = client.with_defaults(DefaultsConfig::default());
+        assert!(client.try_enabled("testing").unwrap());
+    }
+
+    #[test]
+    fn builder_with_url_test() {
+        let client = FunWithFlags::builder().url("null://").build().unwrap();
+
+        assert_eq!("null", client.backend().backend_name());
+    }
+
+    #[test]
+    fn builder_with_backend_type_test() {
+        let client = FunWithFlags::builder()
+            .url("")
+            .backend_type(BackendType::Null)
+            .build()
+            .unwrap();
+
+        assert_eq!("null", client.backend().backend_name());
+    }
+
+    #[test]
+    fn builder_with_unknown_url_test() {
+        assert!(FunWithFlags::builder().url("localhost").build().is_err());
+    }
+}
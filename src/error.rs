@@ -1,53 +1,351 @@
+/// called with the name of the flag and the error when a flag falls back to its default because the backend failed
+pub type ErrorCallback = Box<dyn Fn(&str, &Error) + Send + Sync>;
+
+/// error of a backend library, its type depends on the backend, use `downcast_ref` to inspect it
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The error of every operation, the same type under every feature set.
+///
+/// `kind` tells what went wrong, for example whether a retry can help, the error of the backend library is the `source`.
 #[derive(Debug)]
 pub enum Error {
-    #[cfg(feature = "r2d2")]
-    R2D2(r2d2::Error),
-    #[cfg(feature = "redis-backend")]
-    Redis(redis::RedisError),
-    #[cfg(feature = "postgres-backend")]
-    Postgres(postgres::Error),
+    /// the backend can not be reached, the pool timed out, the connection dropped or the server is down
+    Connection(BoxError),
+    Config(crate::config::ConfigError),
+    /// a stored gate of the flag can not be decoded, `raw` is the stored value
+    Decode {
+        flag: String,
+        gate_type: String,
+        raw: String,
+    },
+    /// the input is rejected before anything is written
+    Validation(ValidationError),
+    /// something that has to exist does not, like the flags file
+    NotFound(String),
+    /// the flags file can not be read or parsed
+    File {
+        path: std::path::PathBuf,
+        message: String,
+    },
+    /// the backend failed to run the operation
+    Backend(BoxError),
     Custom(String),
 }
 
+/// why the input of a write is rejected, see `Error::Validation`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    Empty {
+        field: Field,
+    },
+    /// the value contains a character that the encoding of the backend uses as separator
+    InvalidCharacter {
+        field: Field,
+        value: String,
+        character: char,
+    },
+    /// the value does not fit in the column of the backend
+    TooLong {
+        field: Field,
+        value: String,
+        max: usize,
+    },
+    /// percentages are between 0 and 1, both exclusive, use `enable` for everyone
+    Percentage {
+        flag: String,
+        percentage: f64,
+    },
+    /// the gate has nothing to store, like `FeatureFlag::Empty`
+    NoGate {
+        flag: String,
+    },
+}
+
+/// the input that is validated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    FlagName,
+    ActorId,
+    GroupName,
+    /// the stored target of a gate that is not an actor or a group, like the percentage
+    Target,
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Field::FlagName => write!(f, "flag name"),
+            Field::ActorId => write!(f, "actor id"),
+            Field::GroupName => write!(f, "group name"),
+            Field::Target => write!(f, "gate target"),
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ValidationError::*;
+
+        match self {
+            Empty { field } => write!(f, "{} can not be empty", field),
+            InvalidCharacter {
+                field,
+                value,
+                character,
+            } => write!(f, "{} '{}' can not contain '{}'", field, value, character),
+            TooLong { field, value, max } => {
+                write!(f, "{} '{}' is longer than {} characters", field, value, max)
+            }
+            Percentage { flag, percentage } => write!(
+                f,
+                "percentage of flag {} must be between 0 and 1, got {}",
+                flag, percentage
+            ),
+            NoGate { flag } => write!(f, "flag {} has no gate to set", flag),
+        }
+    }
+}
+
+impl From<ValidationError> for Error {
+    fn from(e: ValidationError) -> Self {
+        Error::Validation(e)
+    }
+}
+
+/// what kind of error happened, see `Error::kind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    Connection,
+    Config,
+    Decode,
+    Validation,
+    NotFound,
+    Backend,
+}
+
+impl ErrorKind {
+    /// true for connection errors, the same operation can succeed when the backend is reachable again
+    pub fn is_retryable(self) -> bool {
+        self == ErrorKind::Connection
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        use Error::*;
+
+        match self {
+            Connection(_) => ErrorKind::Connection,
+            Config(_) | File { .. } => ErrorKind::Config,
+            Decode { .. } => ErrorKind::Decode,
+            Validation(_) => ErrorKind::Validation,
+            NotFound(_) => ErrorKind::NotFound,
+            Backend(_) | Custom(_) => ErrorKind::Backend,
+        }
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use Error::*;
 
         match self {
-            #[cfg(feature = "r2d2")]
-            R2D2(x) => write!(f, "{}", x),
-            #[cfg(feature = "redis-backend")]
-            Redis(x) => write!(f, "{}", x),
-            #[cfg(feature = "postgres-backend")]
-            Postgres(x) => write!(f, "{}", x),
+            Connection(x) => write!(f, "{}", x),
+            Config(x) => write!(f, "{}", x),
+            Decode {
+                flag,
+                gate_type,
+                raw,
+            } => write!(
+                f,
+                "flag {} has a {} gate that can not be decoded: '{}'",
+                flag, gate_type, raw
+            ),
+            Validation(x) => write!(f, "{}", x),
+            NotFound(x) => write!(f, "{}", x),
+            File { path, message } => write!(f, "{}: {}", path.display(), message),
+            Backend(x) => write!(f, "{}", x),
             Custom(x) => write!(f, "{}", x),
         }
     }
 }
 
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connection(x) | Error::Backend(x) => Some(x.as_ref()),
+            Error::Config(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
 impl From<String> for Error {
     fn from(e: String) -> Self {
         Error::Custom(e)
     }
 }
 
+impl From<crate::config::ConfigError> for Error {
+    fn from(e: crate::config::ConfigError) -> Self {
+        Error::Config(e)
+    }
+}
+
+/// the only error of the pool is a timeout while it waits for a connection
 #[cfg(feature = "r2d2")]
 impl From<r2d2::Error> for Error {
     fn from(e: r2d2::Error) -> Self {
-        Error::R2D2(e)
+        Error::Connection(Box::new(e))
     }
 }
 
 #[cfg(feature = "redis-backend")]
 impl From<redis::RedisError> for Error {
     fn from(e: redis::RedisError) -> Self {
-        Error::Redis(e)
+        if e.kind() == redis::ErrorKind::InvalidClientConfig {
+            Error::Config(crate::config::ConfigError::Message(e.to_string()))
+        } else if e.is_io_error()
+            || e.is_connection_refusal()
+            || e.is_connection_dropped()
+            || e.is_timeout()
+        {
+            Error::Connection(Box::new(e))
+        } else {
+            Error::Backend(Box::new(e))
+        }
     }
 }
 
 #[cfg(feature = "postgres-backend")]
 impl From<postgres::Error> for Error {
     fn from(e: postgres::Error) -> Self {
-        Error::Postgres(e)
+        use std::error::Error as _;
+
+        // class 08 is "connection exception"
+        let connection_exception = e.code().is_some_and(|code| code.code().starts_with("08"));
+        let io_error = e
+            .source()
+            .is_some_and(|source| source.is::<std::io::Error>());
+
+        if e.is_closed() || connection_exception || io_error {
+            Error::Connection(Box::new(e))
+        } else {
+            Error::Backend(Box::new(e))
+        }
+    }
+}
+
+#[cfg(feature = "sqlite-backend")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        use rusqlite::ErrorCode::*;
+
+        match e.sqlite_error_code() {
+            Some(CannotOpen) | Some(DatabaseBusy) | Some(DatabaseLocked) => {
+                Error::Connection(Box::new(e))
+            }
+            _ => Error::Backend(Box::new(e)),
+        }
+    }
+}
+
+#[cfg(feature = "mysql-backend")]
+impl From<mysql::Error> for Error {
+    fn from(e: mysql::Error) -> Self {
+        use mysql::DriverError::*;
+
+        match e {
+            mysql::Error::UrlError(_) => {
+                Error::Config(crate::config::ConfigError::Message(e.to_string()))
+            }
+            mysql::Error::IoError(_)
+            | mysql::Error::DriverError(ConnectTimeout)
+            | mysql::Error::DriverError(CouldNotConnect(_))
+            | mysql::Error::DriverError(Timeout) => Error::Connection(Box::new(e)),
+            _ => Error::Backend(Box::new(e)),
+        }
+    }
+}
+
+#[cfg(all(feature = "async", feature = "postgres-backend"))]
+impl From<deadpool_postgres::PoolError> for Error {
+    fn from(e: deadpool_postgres::PoolError) -> Self {
+        Error::Connection(Box::new(e))
+    }
+}
+
+#[cfg(all(feature = "async", feature = "postgres-backend"))]
+impl From<deadpool_postgres::BuildError> for Error {
+    fn from(e: deadpool_postgres::BuildError) -> Self {
+        Error::Config(crate::config::ConfigError::Message(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, ErrorKind, ValidationError};
+    use std::error::Error as _;
+
+    fn refused() -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "connection refused")
+    }
+
+    #[test]
+    fn kinds_and_sources() {
+        let error = Error::Connection(Box::new(refused()));
+        assert_eq!(ErrorKind::Connection, error.kind());
+        assert!(error.kind().is_retryable());
+        assert!(error
+            .source()
+            .and_then(|source| source.downcast_ref::<std::io::Error>())
+            .is_some());
+
+        let error = Error::Decode {
+            flag: String::from("testing"),
+            gate_type: String::from("percentage"),
+            raw: String::from("time/abc"),
+        };
+        assert_eq!(ErrorKind::Decode, error.kind());
+        assert!(!error.kind().is_retryable());
+        assert!(error.source().is_none());
+    }
+
+    #[test]
+    fn converts_into_boxed_errors() {
+        fn set() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            Err(Error::from(ValidationError::Percentage {
+                flag: String::from("testing"),
+                percentage: 1.5,
+            }))?
+        }
+
+        let error = set().unwrap_err();
+        assert_eq!(
+            Some(ErrorKind::Validation),
+            error.downcast_ref::<Error>().map(Error::kind)
+        );
+    }
+
+    #[cfg(feature = "redis-backend")]
+    #[test]
+    fn redis_errors_are_classified() {
+        let error = Error::from(redis::RedisError::from(refused()));
+        assert_eq!(ErrorKind::Connection, error.kind());
+
+        let error = Error::from(redis::RedisError::from((
+            redis::ErrorKind::TypeError,
+            "wrong type",
+        )));
+        assert_eq!(ErrorKind::Backend, error.kind());
+    }
+
+    #[cfg(feature = "sqlite-backend")]
+    #[test]
+    fn sqlite_errors_are_classified() {
+        let error = Error::from(
+            rusqlite::Connection::open("/fun-with-flags/does/not/exist.sqlite").unwrap_err(),
+        );
+        assert_eq!(ErrorKind::Connection, error.kind());
     }
 }
@@ -1,235 +1,165 @@
-use crate::config::{fetch_config, ConfigError};
-use crate::models::GroupSet;
-use crate::{Actor, Backend, DBConnection, FeatureFlag, Group, Output};
-
-pub fn establish_connection() -> Result<DBConnection, ConfigError> {
-    let config = fetch_config().expect("database config is not correctly set");
-
-    let url = config.to_url().ok_or(ConfigError::Message(
-        "database config can not find correct url".into(),
-    ))?;
-    DBConnection::establish(&url).or(Err(ConfigError::Message(format!(
-        "Error connecting to {}",
-        url
-    ))))
-}
-
-/// only used for debug purposes
-pub fn establish_connection_to_database(database_name: &str) -> DBConnection {
-    let config = fetch_config().unwrap();
-
-    if let Some((database_url, _name)) = config.parts() {
-        DBConnection::establish(&format!("{}/{}", database_url, database_name))
-            .expect(&format!("Error connecting to {}", database_url))
-    } else {
-        panic!("DATABASE_URL must be set")
+use crate::backend::null;
+use crate::config::{fetch_config, CacheConfig, DefaultsConfig};
+use crate::{Actor, Error, Evaluation, Flag, FunWithFlags, Group, Output};
+
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+lazy_static::lazy_static! {
+    static ref DEFAULT_CLIENT: RwLock<Option<Arc<FunWithFlags>>> = RwLock::new(None);
+}
+
+/// the client used by the free functions, created from the config on first use
+///
+/// When the client can not be created the error is logged and every flag uses its default from `[defaults]`,
+/// the `try_*`, `enable_*`, `disable_*` and `clear_*` functions return the error instead.
+/// Replace the client with `set_default_client` once the backend is reachable again.
+pub fn default_client() -> Arc<FunWithFlags> {
+    if let Some(client) = DEFAULT_CLIENT.read().unwrap().as_ref() {
+        return client.clone();
     }
+
+    let mut default_client = DEFAULT_CLIENT.write().unwrap();
+    default_client
+        .get_or_insert_with(|| {
+            Arc::new(FunWithFlags::new().unwrap_or_else(|e| {
+                log::error!(
+                    "can not create the default client, flags use their defaults: {}",
+                    e
+                );
+                FunWithFlags::with_backend(null::Unavailable::new(&e))
+                    .with_defaults(config_defaults())
+            }))
+        })
+        .clone()
+}
+
+/// the `[defaults]` section, also when the rest of the config is not valid
+pub(crate) fn config_defaults() -> DefaultsConfig {
+    fetch_config()
+        .map(|config| config.defaults())
+        .unwrap_or_default()
+}
+
+/// replaces the client used by the free functions
+pub fn set_default_client(client: FunWithFlags) {
+    *DEFAULT_CLIENT.write().unwrap() = Some(Arc::new(client));
+}
+
+pub fn configure_cache(cache: CacheConfig) -> Result<(), Error> {
+    default_client().configure_cache(cache)
+}
+
+pub fn get_flag(flag: &str) -> Result<Flag, Error> {
+    default_client().get_flag(flag)
+}
+
+pub fn all_flag_names() -> Result<HashSet<String>, Error> {
+    default_client().all_flag_names()
+}
+
+pub fn all_flags() -> Result<Vec<Flag>, Error> {
+    default_client().all_flags()
 }
 
 pub fn enable(flag: &str) -> Output {
-    let conn = establish_connection().unwrap();
-    Backend::set(
-        &conn,
-        FeatureFlag::Boolean {
-            name: flag.to_string(),
-            enabled: true,
-        },
-    )
+    default_client().enable(flag)
 }
 
 pub fn enable_for<T: Actor>(flag: &str, actor: &T) -> Output {
-    let conn = establish_connection().unwrap();
-    Backend::set(
-        &conn,
-        FeatureFlag::Actor {
-            name: flag.to_string(),
-            target: actor.feature_flag_id(),
-            enabled: true,
-        },
-    )
+    default_client().enable_for(flag, actor)
 }
 
 pub fn disable(flag: &str) -> Output {
-    let conn = establish_connection().unwrap();
-    Backend::set(
-        &conn,
-        FeatureFlag::Boolean {
-            name: flag.to_string(),
-            enabled: false,
-        },
-    )
+    default_client().disable(flag)
 }
 
 pub fn disable_for<T: Actor>(flag: &str, actor: &T) -> Output {
-    let conn = establish_connection().unwrap();
-    Backend::set(
-        &conn,
-        FeatureFlag::Actor {
-            name: flag.to_string(),
-            target: actor.feature_flag_id(),
-            enabled: false,
-        },
-    )
+    default_client().disable_for(flag, actor)
 }
 
 pub fn enabled(flag: &str) -> bool {
-    let conn = establish_connection().unwrap();
-
-    if let Ok(FeatureFlag::Boolean { enabled: true, .. }) = Backend::get(
-        &conn,
-        FeatureFlag::Boolean {
-            name: flag.to_string(),
-            enabled: true,
-        },
-    ) {
-        return true;
-    };
+    default_client().enabled(flag)
+}
 
-    if let Ok(FeatureFlag::Time {
-        target,
-        enabled: true,
-        ..
-    }) = Backend::get(
-        &conn,
-        FeatureFlag::Time {
-            name: flag.to_string(),
-            enabled: true,
-            target: 0.0,
-        },
-    ) {
-        return target > generate_0_1();
-    };
+pub fn try_enabled(flag: &str) -> Result<bool, Error> {
+    default_client().try_enabled(flag)
+}
+
+pub fn evaluate(flag: &str) -> Result<Evaluation, Error> {
+    default_client().evaluate(flag)
+}
 
-    false
+pub fn enabled_or(flag: &str, default: bool) -> bool {
+    default_client().enabled_or(flag, default)
 }
 
 pub fn disabled(flag: &str) -> bool {
-    !enabled(flag)
+    default_client().disabled(flag)
 }
 
 pub fn enabled_for<T: Actor + Group>(flag: &str, actor: &T) -> bool {
-    let conn = establish_connection().unwrap();
-
-    if let Ok(FeatureFlag::Actor { enabled: true, .. }) = Backend::get(
-        &conn,
-        FeatureFlag::Actor {
-            name: flag.to_string(),
-            target: actor.feature_flag_id(),
-            enabled: true,
-        },
-    ) {
-        return true;
-    };
+    default_client().enabled_for(flag, actor)
+}
 
-    if let Ok(FeatureFlag::Group {
-        target,
-        enabled: true,
-        ..
-    }) = Backend::get(
-        &conn,
-        FeatureFlag::Group {
-            name: flag.to_string(),
-            target: GroupSet::default(),
-            enabled: true,
-        },
-    ) {
-        return target.check(actor);
-    };
+pub fn enabled_for_or<T: Actor + Group>(flag: &str, actor: &T, default: bool) -> bool {
+    default_client().enabled_for_or(flag, actor, default)
+}
 
-    if let Ok(FeatureFlag::Percentage {
-        target,
-        enabled: true,
-        ..
-    }) = Backend::get(
-        &conn,
-        FeatureFlag::Percentage {
-            name: flag.to_string(),
-            enabled: true,
-            target: 0.0,
-        },
-    ) {
-        let score = score(flag, actor);
-        println!("{}", score);
-        return target > score;
-    };
+pub fn try_enabled_for<T: Actor + Group>(flag: &str, actor: &T) -> Result<bool, Error> {
+    default_client().try_enabled_for(flag, actor)
+}
 
-    false
+pub fn evaluate_for<T: Actor + Group>(flag: &str, actor: &T) -> Result<Evaluation, Error> {
+    default_client().evaluate_for(flag, actor)
 }
 
 pub fn disabled_for<T: Actor + Group>(flag: &str, actor: &T) -> bool {
-    !enabled_for(flag, actor)
+    default_client().disabled_for(flag, actor)
 }
 
 pub fn enable_percentage_of_time(flag: &str, percentage: f64) -> Output {
-    let conn = establish_connection().unwrap();
-    Backend::set(
-        &conn,
-        FeatureFlag::Time {
-            name: flag.to_string(),
-            target: percentage,
-            enabled: true,
-        },
-    )
+    default_client().enable_percentage_of_time(flag, percentage)
 }
 
 pub fn disable_percentage_of_time(flag: &str) -> Output {
-    let conn = establish_connection().unwrap();
-    Backend::set(
-        &conn,
-        FeatureFlag::Time {
-            name: flag.to_string(),
-            target: 0.0,
-            enabled: false,
-        },
-    )
+    default_client().disable_percentage_of_time(flag)
 }
 
 pub fn enable_percentage_of_actors(flag: &str, percentage: f64) -> Output {
-    let conn = establish_connection().unwrap();
-    Backend::set(
-        &conn,
-        FeatureFlag::Percentage {
-            name: flag.to_string(),
-            target: percentage,
-            enabled: true,
-        },
-    )
+    default_client().enable_percentage_of_actors(flag, percentage)
 }
 
 pub fn disable_percentage_of_actors(flag: &str) -> Output {
-    let conn = establish_connection().unwrap();
-    Backend::set(
-        &conn,
-        FeatureFlag::Percentage {
-            name: flag.to_string(),
-            target: 0.0,
-            enabled: false,
-        },
-    )
+    default_client().disable_percentage_of_actors(flag)
 }
 
 pub fn enable_for_group(flag: &str, group_name: &str) -> Output {
-    let conn = establish_connection().unwrap();
-    Backend::set(
-        &conn,
-        FeatureFlag::Group {
-            name: flag.to_string(),
-            target: GroupSet::new(group_name.to_string()),
-            enabled: true,
-        },
-    )
+    default_client().enable_for_group(flag, group_name)
 }
 
 pub fn disable_for_group(flag: &str, group_name: &str) -> Output {
-    let conn = establish_connection().unwrap();
-    Backend::set(
-        &conn,
-        FeatureFlag::Group {
-            name: flag.to_string(),
-            target: GroupSet::new(group_name.to_string()),
-            enabled: false,
-        },
-    )
+    default_client().disable_for_group(flag, group_name)
+}
+
+pub fn clear(flag: &str) -> Result<(), Error> {
+    default_client().clear(flag)
+}
+
+pub fn clear_for<T: Actor>(flag: &str, actor: &T) -> Result<(), Error> {
+    default_client().clear_for(flag, actor)
+}
+
+pub fn clear_for_group(flag: &str, group_name: &str) -> Result<(), Error> {
+    default_client().clear_for_group(flag, group_name)
+}
+
+pub fn clear_boolean(flag: &str) -> Result<(), Error> {
+    default_client().clear_boolean(flag)
+}
+
+pub fn clear_percentage(flag: &str) -> Result<(), Error> {
+    default_client().clear_percentage(flag)
 }
 
 pub fn score<T: Actor>(flag: &str, actor: &T) -> f64 {
@@ -248,7 +178,7 @@ fn hash(input: &str) -> f64 {
     num as f64 / 65_536f64
 }
 
-fn generate_0_1() -> f64 {
+pub(crate) fn generate_0_1() -> f64 {
     use rand::distributions::OpenClosed01;
     use rand::{thread_rng, Rng};
 
@@ -257,9 +187,10 @@ fn generate_0_1() -> f64 {
 
 #[cfg(test)]
 mod tests {
+    use crate::backend::MockStorageBackend;
     use crate::{
-        enable, enable_for, enable_for_group, enable_percentage_of_actors, enabled_for, Actor,
-        Backend, FeatureFlag, Group,
+        enable, enable_for, enable_for_group, enable_percentage_of_actors, enabled, enabled_for,
+        set_default_client, try_enabled, Actor, Error, FeatureFlag, Flag, FunWithFlags, Group,
     };
 
     use crate::models::GroupSet;
@@ -303,26 +234,26 @@ mod tests {
     #[test]
     #[serial]
     fn enable_test() {
-        let _mock = Backend::default();
+        let mut mock = MockStorageBackend::new();
 
-        let ctx = Backend::set_context();
-        ctx.expect().returning(|_, _| {
+        mock.expect_set().returning(|_| {
             Ok(FeatureFlag::Boolean {
                 name: "oke".to_string(),
                 enabled: true,
             })
         });
 
+        set_default_client(FunWithFlags::with_backend(mock));
+
         assert!(enable("oke").is_ok());
     }
 
     #[test]
     #[serial]
     fn enable_for_test() {
-        let _mock = Backend::default();
+        let mut mock = MockStorageBackend::new();
 
-        let ctx = Backend::set_context();
-        ctx.expect().returning(|_, _| {
+        mock.expect_set().returning(|_| {
             Ok(FeatureFlag::Actor {
                 name: "oke".to_string(),
                 target: "testing".to_string(),
@@ -330,31 +261,36 @@ mod tests {
             })
         });
 
+        set_default_client(FunWithFlags::with_backend(mock));
+
         assert!(enable_for("oke", &"testing").is_ok());
     }
 
     #[test]
     #[serial]
     fn percentage_enable_for_test() {
-        let _mock = Backend::default();
+        let mut mock = MockStorageBackend::new();
 
-        let ctx = Backend::set_context();
-        ctx.expect().returning(|_, _| {
+        mock.expect_set().returning(|_| {
             Ok(FeatureFlag::Percentage {
                 name: "testing".to_string(),
                 target: 0.40,
                 enabled: true,
             })
         });
-        let ctx = Backend::get_context();
-        ctx.expect().returning(|_, _| {
-            Ok(FeatureFlag::Percentage {
-                name: "testing".to_string(),
-                target: 0.40,
-                enabled: true,
-            })
+        mock.expect_get_flag().returning(|name| {
+            Ok(Flag::new(
+                name,
+                vec![FeatureFlag::Percentage {
+                    name: "testing".to_string(),
+                    target: 0.40,
+                    enabled: true,
+                }],
+            ))
         });
 
+        set_default_client(FunWithFlags::with_backend(mock));
+
         enable_percentage_of_actors("testing", 0.40).unwrap();
 
         assert!(enabled_for("testing", &"test"));
@@ -363,33 +299,33 @@ mod tests {
     #[test]
     #[serial]
     fn enable_for_group_test() {
-        let _mock = Backend::default();
+        let mut mock = MockStorageBackend::new();
 
-        let ctx = Backend::set_context();
-        ctx.expect().returning(|_, _| {
+        mock.expect_set().returning(|_| {
             Ok(FeatureFlag::Group {
                 name: "testing".to_string(),
                 target: GroupSet::new("tests".to_string()),
                 enabled: true,
             })
         });
-        let ctx = Backend::get_context();
-        ctx.expect().returning(|_, _| {
-            Ok(FeatureFlag::Group {
-                name: "testing".to_string(),
-                target: GroupSet::new("tests".to_string()),
-                enabled: true,
-            })
+        mock.expect_get_flag().returning(|name| {
+            Ok(Flag::new(
+                name,
+                vec![FeatureFlag::Group {
+                    name: "testing".to_string(),
+                    target: GroupSet::new("tests".to_string()),
+                    enabled: true,
+                }],
+            ))
         });
 
+        set_default_client(FunWithFlags::with_backend(mock));
+
         struct Test;
 
         impl Group for Test {
             fn is_in_group(&self, group_name: &str) -> bool {
-                match group_name {
-                    "tests" => true,
-                    _ => false,
-                }
+                group_name == "tests"
             }
         }
 
@@ -403,4 +339,18 @@ mod tests {
 
         assert!(enabled_for("testing", &Test {}));
     }
+
+    #[test]
+    #[serial]
+    fn unavailable_client_returns_errors_test() {
+        use crate::backend::null;
+
+        let error = Error::Custom(String::from("no database configured"));
+        set_default_client(FunWithFlags::with_backend(null::Unavailable::new(&error)));
+
+        assert!(!enabled("testing"));
+        let error = try_enabled("testing").unwrap_err().to_string();
+        assert!(error.contains("no database configured"), "{}", error);
+        assert!(enable("testing").is_err());
+    }
 }
@@ -1,5 +1,9 @@
-use crate::Group;
+use crate::config::DecodePolicy;
+use crate::error::{Field, ValidationError};
+use crate::functions::{generate_0_1, score};
+use crate::{Actor, Error, Group};
 use std::collections::HashSet;
+use std::convert::TryFrom;
 
 #[derive(Debug)]
 pub struct RawFeatureFlag {
@@ -9,7 +13,7 @@ pub struct RawFeatureFlag {
     pub enabled: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct RawOptionalFeatureFlags {
     pub flag_name: Option<String>,
     pub data: Vec<RawOptionalFeatureFlag>,
@@ -31,81 +35,103 @@ impl RawOptionalFeatureFlags {
         }
         self.name_set = true;
     }
+}
 
-    pub fn find(self, flag: &FeatureFlag) -> Option<FeatureFlag> {
-        assert!(self.name_set);
+/// the gates that decode go to `gates`, the ones that do not to `invalid_gates`
+impl TryFrom<RawOptionalFeatureFlags> for Flag {
+    type Error = Error;
 
-        self.data.into_iter().find_map(|x| {
-            let result = FeatureFlag::from(x);
-            if flag.same(&result) {
-                Some(result)
-            } else {
-                None
-            }
-        })
-    }
-}
+    fn try_from(mut flags: RawOptionalFeatureFlags) -> Result<Flag, Error> {
+        if !flags.name_set {
+            flags.update_flag_name();
+        }
 
-impl From<RawOptionalFeatureFlags> for Vec<FeatureFlag> {
-    fn from(flags: RawOptionalFeatureFlags) -> Vec<FeatureFlag> {
-        flags
-            .data
-            .into_iter()
-            .map(|x| FeatureFlag::from(x))
-            .collect()
+        Flag::decode(
+            flags.flag_name.clone().unwrap_or_default(),
+            flags.data.into_iter().map(FeatureFlag::try_from),
+        )
     }
 }
 
-impl Default for RawOptionalFeatureFlags {
-    fn default() -> Self {
-        RawOptionalFeatureFlags {
-            flag_name: None,
-            data: Vec::new(),
-            name_set: false,
-        }
+/// fails on the first gate that can not be decoded
+impl TryFrom<RawOptionalFeatureFlags> for Vec<FeatureFlag> {
+    type Error = Error;
+
+    fn try_from(flags: RawOptionalFeatureFlags) -> Result<Vec<FeatureFlag>, Error> {
+        flags.data.into_iter().map(FeatureFlag::try_from).collect()
     }
 }
 
+/// a field of the redis hash of a flag with its value, as it is stored
 #[derive(Debug)]
 pub struct RawOptionalFeatureFlag {
     pub flag_name: Option<String>,
-    pub gate_type: String,
-    pub target: String,
-    pub enabled: bool,
+    pub field: String,
+    pub value: String,
 }
 
-impl From<RawOptionalFeatureFlag> for FeatureFlag {
-    fn from(flags: RawOptionalFeatureFlag) -> FeatureFlag {
-        match flags.gate_type.as_ref() {
-            "boolean" => FeatureFlag::Boolean {
-                name: flags.flag_name.unwrap(),
-                enabled: flags.enabled,
-            },
-            "actor" => FeatureFlag::Actor {
-                name: flags.flag_name.unwrap(),
-                target: flags.target,
-                enabled: flags.enabled,
-            },
-            "group" => FeatureFlag::Group {
-                name: flags.flag_name.unwrap(),
-                target: GroupSet::new(flags.target),
-                enabled: flags.enabled,
-            },
-            "time" => FeatureFlag::Time {
-                name: flags.flag_name.unwrap(),
-                target: flags.target.parse().expect("db contains invalid data"),
-                enabled: flags.enabled,
-            },
-            "actors" => FeatureFlag::Percentage {
-                name: flags.flag_name.unwrap(),
-                target: flags.target.parse().expect("db contains invalid data"),
-                enabled: flags.enabled,
+impl TryFrom<RawOptionalFeatureFlag> for FeatureFlag {
+    type Error = Error;
+
+    /// the gate that is stored in the field, see `FeatureFlag::to_redis_values`
+    fn try_from(raw: RawOptionalFeatureFlag) -> Result<FeatureFlag, Error> {
+        let RawOptionalFeatureFlag {
+            flag_name,
+            field,
+            value,
+        } = raw;
+        let name = flag_name.unwrap_or_default();
+        let enabled = value.parse::<bool>().ok();
+
+        let decoded = match (field.as_str(), enabled) {
+            ("boolean", Some(enabled)) => Some(FeatureFlag::Boolean {
+                name: name.clone(),
+                enabled,
+            }),
+            ("percentage", _) => decode_percentage(&name, &value, true),
+            (field, Some(enabled)) => match field.split_once('/') {
+                Some(("actor", target)) => Some(FeatureFlag::Actor {
+                    name: name.clone(),
+                    target: target.to_string(),
+                    enabled,
+                }),
+                Some(("group", target)) => Some(FeatureFlag::Group {
+                    name: name.clone(),
+                    target: GroupSet::new(target.to_string()),
+                    enabled,
+                }),
+                _ => None,
             },
-            other_gate => panic!(format!("this gate ({}) is not supported", other_gate)),
-        }
+            _ => None,
+        };
+
+        decoded.ok_or(Error::Decode {
+            flag: name,
+            gate_type: field,
+            raw: value,
+        })
     }
 }
 
+/// the percentage gate stored as `time/<percentage>` or `actors/<percentage>`, `None` if it is neither
+pub(crate) fn decode_percentage(name: &str, target: &str, enabled: bool) -> Option<FeatureFlag> {
+    let percentage = |prefix: &str| target.strip_prefix(prefix)?.parse::<f64>().ok();
+
+    if let Some(target) = percentage("time/") {
+        return Some(FeatureFlag::Time {
+            name: name.to_string(),
+            target,
+            enabled,
+        });
+    }
+
+    percentage("actors/").map(|target| FeatureFlag::Percentage {
+        name: name.to_string(),
+        target,
+        enabled,
+    })
+}
+
 #[derive(Debug, Clone)]
 pub enum FeatureFlag {
     Boolean {
@@ -135,56 +161,282 @@ pub enum FeatureFlag {
     Empty,
 }
 
+/// A flag with all its gates, loaded from the backend in one go.
+///
+/// Evaluating the flag happens in memory, same as the `FunWithFlags.Flag` struct in the elixir library.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Flag {
+    pub name: String,
+    pub gates: Vec<FeatureFlag>,
+    /// gates that are stored but can not be decoded, they are never evaluated
+    pub invalid_gates: Vec<InvalidGate>,
+}
+
+/// A stored gate that can not be decoded, what evaluating its flag does is decided by the `DecodePolicy`.
 #[derive(Debug, Clone, PartialEq)]
+pub struct InvalidGate {
+    pub gate_type: String,
+    pub raw: String,
+}
+
+impl InvalidGate {
+    /// the `Error::Decode` of the gate
+    pub fn error(&self, flag_name: &str) -> Error {
+        Error::Decode {
+            flag: flag_name.to_string(),
+            gate_type: self.gate_type.clone(),
+            raw: self.raw.clone(),
+        }
+    }
+}
+
+impl Flag {
+    pub fn new<S: Into<String>>(name: S, gates: Vec<FeatureFlag>) -> Flag {
+        Flag {
+            name: name.into(),
+            gates,
+            invalid_gates: Vec::new(),
+        }
+    }
+
+    /// the flag with the decoded gates, the gates that failed with `Error::Decode` are kept in `invalid_gates`
+    ///
+    /// Other errors are returned.
+    pub fn decode<S, I>(name: S, gates: I) -> Result<Flag, Error>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = Result<FeatureFlag, Error>>,
+    {
+        let mut flag = Flag::new(name, vec![]);
+        for gate in gates {
+            flag.push_decoded(gate)?;
+        }
+
+        Ok(flag)
+    }
+
+    pub(crate) fn push_decoded(&mut self, gate: Result<FeatureFlag, Error>) -> Result<(), Error> {
+        match gate {
+            Ok(gate) => self.gates.push(gate),
+            Err(Error::Decode { gate_type, raw, .. }) => {
+                self.invalid_gates.push(InvalidGate { gate_type, raw })
+            }
+            Err(e) => return Err(e),
+        }
+
+        Ok(())
+    }
+
+    /// applies the policy to the invalid gates, fails with the first one for `DecodePolicy::Fail`
+    pub fn check_decoded(&self, policy: DecodePolicy) -> Result<(), Error> {
+        match policy {
+            DecodePolicy::Skip => Ok(()),
+            DecodePolicy::Log => {
+                for gate in self.invalid_gates.iter() {
+                    log::warn!("{}, the gate is skipped", gate.error(&self.name));
+                }
+                Ok(())
+            }
+            DecodePolicy::Fail => match self.invalid_gates.first() {
+                Some(gate) => Err(gate.error(&self.name)),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// true if the flag has no gates, also no invalid ones
+    pub fn is_empty(&self) -> bool {
+        self.gates.is_empty() && self.invalid_gates.is_empty()
+    }
+
+    /// the gate that is the `same` as `flag`, `FeatureFlag::Empty` if the flag does not have it
+    ///
+    /// For a group gate the stored gates of all the groups in `flag` are merged into one gate.
+    pub fn find(&self, flag: &FeatureFlag) -> FeatureFlag {
+        if let FeatureFlag::Group { target, .. } = flag {
+            return self.find_groups(target);
+        }
+
+        self.gates
+            .iter()
+            .find(|gate| flag.same(gate))
+            .cloned()
+            .unwrap_or(FeatureFlag::Empty)
+    }
+
+    fn find_groups(&self, groups: &GroupSet) -> FeatureFlag {
+        let mut found = GroupSet::default();
+        let mut all_enabled = true;
+
+        for gate in self.gates.iter() {
+            if let FeatureFlag::Group {
+                target, enabled, ..
+            } = gate
+            {
+                if target.iter().all(|group| groups.contains(group)) {
+                    found.extend(target.iter().cloned());
+                    all_enabled &= *enabled;
+                }
+            }
+        }
+
+        if found.is_empty() {
+            FeatureFlag::Empty
+        } else {
+            FeatureFlag::Group {
+                name: self.name.clone(),
+                target: found,
+                enabled: all_enabled,
+            }
+        }
+    }
+
+    /// enabled when the boolean gate is enabled, otherwise the percentage of time gate decides
+    pub fn enabled(&self) -> bool {
+        self.boolean_gate() == Some(true) || self.percentage_of_time_gate()
+    }
+
+    /// checks the gates in the same order as the elixir library: actor, group, boolean and then percentage.
+    ///
+    /// The first gate that applies to the actor decides, so an actor or group that is explicitly disabled
+    /// stays disabled even if the flag is enabled globally. Like in the elixir library a disabled boolean gate
+    /// does not decide, the percentage gates are still checked.
+    pub fn enabled_for<T: Actor + Group>(&self, actor: &T) -> bool {
+        self.gate_for(actor)
+            .unwrap_or_else(|| self.percentage_gate(actor))
+    }
+
+    /// the actor, group or enabled boolean gate that decides for the actor, `None` if only the percentage gates are left
+    pub(crate) fn gate_for<T: Actor + Group>(&self, actor: &T) -> Option<bool> {
+        self.actor_gate(actor)
+            .or_else(|| self.group_gate(actor))
+            .or_else(|| self.boolean_gate().filter(|enabled| *enabled))
+    }
+
+    pub(crate) fn boolean_gate(&self) -> Option<bool> {
+        self.gates.iter().find_map(|gate| match gate {
+            FeatureFlag::Boolean { enabled, .. } => Some(*enabled),
+            _ => None,
+        })
+    }
+
+    fn actor_gate<T: Actor>(&self, actor: &T) -> Option<bool> {
+        let id = actor.feature_flag_id();
+
+        self.gates.iter().find_map(|gate| match gate {
+            FeatureFlag::Actor {
+                target, enabled, ..
+            } if *target == id => Some(*enabled),
+            _ => None,
+        })
+    }
+
+    /// an actor can be in several groups, one disabled group wins over the enabled ones
+    fn group_gate<T: Group>(&self, actor: &T) -> Option<bool> {
+        self.gates
+            .iter()
+            .filter_map(|gate| match gate {
+                FeatureFlag::Group {
+                    target, enabled, ..
+                } if target.check(actor) => Some(*enabled),
+                _ => None,
+            })
+            .reduce(|left, right| left && right)
+    }
+
+    fn percentage_of_time_gate(&self) -> bool {
+        self.gates.iter().any(|gate| match gate {
+            FeatureFlag::Time {
+                target,
+                enabled: true,
+                ..
+            } => *target > generate_0_1(),
+            _ => false,
+        })
+    }
+
+    fn percentage_gate<T: Actor>(&self, actor: &T) -> bool {
+        self.gates.iter().any(|gate| match gate {
+            FeatureFlag::Percentage {
+                target,
+                enabled: true,
+                ..
+            } => *target > score(&self.name, actor),
+            FeatureFlag::Time {
+                target,
+                enabled: true,
+                ..
+            } => *target > generate_0_1(),
+            _ => false,
+        })
+    }
+}
+
+fn check_not_empty(field: Field, value: &str) -> Result<(), Error> {
+    if value.is_empty() {
+        return Err(ValidationError::Empty { field }.into());
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct GroupSet {
     data: HashSet<String>,
-    length: usize,
 }
 
 impl GroupSet {
     pub fn new(one: String) -> Self {
         let mut set = HashSet::new();
         set.insert(one);
-        GroupSet {
-            data: set,
-            length: 1,
-        }
+        GroupSet { data: set }
     }
 
     pub fn check<T: Group>(&self, to_check: &T) -> bool {
         self.data.iter().any(|x| to_check.is_in_group(x))
     }
 
+    pub fn contains(&self, group_name: &str) -> bool {
+        self.data.contains(group_name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.data.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
     pub fn to_optional_string(&self) -> Option<&str> {
-        if self.length == 1 {
-            let mut iter = self.data.iter();
-            iter.next().map(|x| (*x).as_ref())
+        if self.data.len() == 1 {
+            self.data.iter().next().map(|x| x.as_ref())
         } else {
             None
         }
     }
+}
 
-    pub fn get_first_unsafe(&self) -> &str {
-        self.to_optional_string()
-            .expect("setting flag in redis can only be done with one group at a time")
+impl From<HashSet<String>> for GroupSet {
+    fn from(input: HashSet<String>) -> GroupSet {
+        GroupSet { data: input }
     }
 }
 
-impl Default for GroupSet {
-    fn default() -> Self {
-        GroupSet {
-            data: HashSet::new(),
-            length: 0,
-        }
+impl Extend<String> for GroupSet {
+    fn extend<I: IntoIterator<Item = String>>(&mut self, iter: I) {
+        self.data.extend(iter)
     }
 }
 
-impl From<HashSet<String>> for GroupSet {
-    fn from(input: HashSet<String>) -> GroupSet {
-        let length = input.len();
+impl std::iter::FromIterator<String> for GroupSet {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> GroupSet {
         GroupSet {
-            data: input,
-            length,
+            data: iter.into_iter().collect(),
         }
     }
 }
@@ -196,7 +448,26 @@ impl PartialEq for FeatureFlag {
 }
 
 impl FeatureFlag {
-    pub fn enabled<'a>(&'a self) -> &'a bool {
+    /// splits a group gate into one gate per group, that is how the backends store them
+    pub fn split_groups(&self) -> Vec<FeatureFlag> {
+        match self {
+            FeatureFlag::Group {
+                name,
+                target,
+                enabled,
+            } => target
+                .iter()
+                .map(|group| FeatureFlag::Group {
+                    name: name.clone(),
+                    target: GroupSet::new(group.clone()),
+                    enabled: *enabled,
+                })
+                .collect(),
+            other => vec![other.clone()],
+        }
+    }
+
+    pub fn enabled(&self) -> &bool {
         use FeatureFlag::*;
 
         match self {
@@ -209,7 +480,46 @@ impl FeatureFlag {
         }
     }
 
-    pub fn name<'a>(&'a self) -> &'a str {
+    /// checks the gate before it is stored: the names are not empty and the percentage of an enabled gate is between 0 and 1
+    ///
+    /// The backends check the constraints of their encoding on top of this, like the separators of the redis keys.
+    pub fn validate(&self) -> Result<(), Error> {
+        use FeatureFlag::*;
+
+        let no_gate = || ValidationError::NoGate {
+            flag: self.name().to_string(),
+        };
+
+        if let Empty = self {
+            return Err(no_gate().into());
+        }
+        check_not_empty(Field::FlagName, self.name())?;
+
+        match self {
+            Actor { target, .. } => check_not_empty(Field::ActorId, target),
+            Group { target, .. } if target.is_empty() => Err(no_gate().into()),
+            Group { target, .. } => target
+                .iter()
+                .try_for_each(|group| check_not_empty(Field::GroupName, group)),
+            Time {
+                name,
+                target,
+                enabled: true,
+            }
+            | Percentage {
+                name,
+                target,
+                enabled: true,
+            } if !(*target > 0.0 && *target < 1.0) => Err(ValidationError::Percentage {
+                flag: name.clone(),
+                percentage: *target,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
         use FeatureFlag::*;
 
         match self {
@@ -227,50 +537,387 @@ impl FeatureFlag {
 
         match self {
             Boolean { name, .. } => match other {
-                &Boolean {
-                    name: ref other_name,
-                    ..
+                Boolean {
+                    name: other_name, ..
                 } => other_name == name,
                 _ => false,
             },
 
             Time { name, .. } => match other {
-                &Time {
-                    name: ref other_name,
-                    ..
+                Time {
+                    name: other_name, ..
                 } => other_name == name,
                 _ => false,
             },
 
             Percentage { name, .. } => match other {
-                &Percentage {
-                    name: ref other_name,
-                    ..
+                Percentage {
+                    name: other_name, ..
                 } => other_name == name,
                 _ => false,
             },
 
             Actor { name, target, .. } => match other {
-                &Actor {
-                    name: ref stored_name,
-                    target: ref stored_target,
+                Actor {
+                    name: stored_name,
+                    target: stored_target,
                     ..
                 } => stored_name == name && stored_target == target,
                 _ => false,
             },
 
-            Group { name, .. } => match other {
-                &Group {
-                    name: ref stored_name,
+            Group { name, target, .. } => match other {
+                Group {
+                    name: stored_name,
+                    target: stored_target,
                     ..
-                } => stored_name == name,
+                } => stored_name == name && stored_target == target,
                 _ => false,
             },
 
-            Empty => match other {
-                &Empty => true,
-                _ => false,
+            Empty => matches!(other, Empty),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FeatureFlag, Flag, GroupSet, RawOptionalFeatureFlag, RawOptionalFeatureFlags};
+    use crate::config::DecodePolicy;
+    use crate::{Actor, Error, Group};
+
+    use std::convert::TryFrom;
+
+    struct Banned;
+
+    impl Actor for Banned {
+        fn feature_flag_id(&self) -> String {
+            String::from("banned-actor")
+        }
+    }
+
+    impl Group for Banned {
+        fn is_in_group(&self, group_name: &str) -> bool {
+            group_name == "banned"
+        }
+    }
+
+    fn flag(gates: Vec<FeatureFlag>) -> Flag {
+        Flag::new("testing", gates)
+    }
+
+    #[test]
+    fn empty_flag_is_disabled() {
+        let flag = flag(vec![]);
+
+        assert!(!flag.enabled());
+        assert!(!flag.enabled_for(&"actor"));
+    }
+
+    #[test]
+    fn boolean_gate_applies_to_actors() {
+        let flag = flag(vec![FeatureFlag::Boolean {
+            name: "testing".to_string(),
+            enabled: true,
+        }]);
+
+        assert!(flag.enabled());
+        assert!(flag.enabled_for(&"actor"));
+    }
+
+    #[test]
+    fn disabled_boolean_falls_through_to_percentage() {
+        let flag = flag(vec![
+            FeatureFlag::Boolean {
+                name: "testing".to_string(),
+                enabled: false,
+            },
+            FeatureFlag::Percentage {
+                name: "testing".to_string(),
+                target: 0.999,
+                enabled: true,
+            },
+        ]);
+
+        assert!(flag.enabled_for(&"actor"));
+    }
+
+    #[test]
+    fn disabled_boolean_falls_through_to_percentage_of_time() {
+        let flag = flag(vec![
+            FeatureFlag::Boolean {
+                name: "testing".to_string(),
+                enabled: false,
+            },
+            FeatureFlag::Time {
+                name: "testing".to_string(),
+                target: 0.999,
+                enabled: true,
+            },
+        ]);
+
+        assert!((0..40).any(|_| flag.enabled()));
+        assert!((0..40).any(|_| flag.enabled_for(&"actor")));
+    }
+
+    #[test]
+    fn disabled_actor_overrides_boolean() {
+        let flag = flag(vec![
+            FeatureFlag::Boolean {
+                name: "testing".to_string(),
+                enabled: true,
+            },
+            FeatureFlag::Actor {
+                name: "testing".to_string(),
+                target: "actor".to_string(),
+                enabled: false,
+            },
+        ]);
+
+        assert!(flag.enabled());
+        assert!(!flag.enabled_for(&"actor"));
+        assert!(flag.enabled_for(&"other"));
+    }
+
+    #[test]
+    fn disabled_group_overrides_boolean() {
+        let flag = flag(vec![
+            FeatureFlag::Boolean {
+                name: "testing".to_string(),
+                enabled: true,
             },
+            FeatureFlag::Group {
+                name: "testing".to_string(),
+                target: GroupSet::new("banned".to_string()),
+                enabled: false,
+            },
+        ]);
+
+        assert!(!flag.enabled_for(&Banned));
+        assert!(flag.enabled_for(&"other"));
+    }
+
+    #[test]
+    fn disabled_group_wins_over_enabled_group() {
+        struct BetaUser;
+
+        impl Actor for BetaUser {
+            fn feature_flag_id(&self) -> String {
+                String::from("beta-user")
+            }
+        }
+
+        impl Group for BetaUser {
+            fn is_in_group(&self, group_name: &str) -> bool {
+                group_name == "beta"
+            }
+        }
+
+        struct BannedBetaUser;
+
+        impl Actor for BannedBetaUser {
+            fn feature_flag_id(&self) -> String {
+                String::from("banned-beta-user")
+            }
+        }
+
+        impl Group for BannedBetaUser {
+            fn is_in_group(&self, group_name: &str) -> bool {
+                group_name == "beta" || group_name == "banned"
+            }
         }
+
+        let flag = flag(vec![
+            FeatureFlag::Group {
+                name: "testing".to_string(),
+                target: GroupSet::new("beta".to_string()),
+                enabled: true,
+            },
+            FeatureFlag::Group {
+                name: "testing".to_string(),
+                target: GroupSet::new("banned".to_string()),
+                enabled: false,
+            },
+        ]);
+
+        assert!(flag.enabled_for(&BetaUser));
+        assert!(!flag.enabled_for(&BannedBetaUser));
+        assert!(!flag.enabled_for(&Banned));
+        assert!(!flag.enabled_for(&"other"));
+    }
+
+    #[test]
+    fn find_merges_group_gates() {
+        let flag = flag(vec![
+            FeatureFlag::Group {
+                name: "testing".to_string(),
+                target: GroupSet::new("beta".to_string()),
+                enabled: true,
+            },
+            FeatureFlag::Group {
+                name: "testing".to_string(),
+                target: GroupSet::new("alpha".to_string()),
+                enabled: true,
+            },
+            FeatureFlag::Group {
+                name: "testing".to_string(),
+                target: GroupSet::new("banned".to_string()),
+                enabled: false,
+            },
+        ]);
+
+        let groups: GroupSet = vec!["alpha".to_string(), "beta".to_string()]
+            .into_iter()
+            .collect();
+        let request = FeatureFlag::Group {
+            name: "testing".to_string(),
+            target: groups.clone(),
+            enabled: true,
+        };
+
+        match flag.find(&request) {
+            FeatureFlag::Group {
+                target, enabled, ..
+            } => {
+                assert_eq!(groups, target);
+                assert!(enabled);
+            }
+            other => panic!("expected group gate, got {:?}", other),
+        }
+
+        assert_eq!(2, request.split_groups().len());
+    }
+
+    #[test]
+    fn find_returns_empty_for_missing_gate() {
+        let flag = flag(vec![FeatureFlag::Boolean {
+            name: "testing".to_string(),
+            enabled: true,
+        }]);
+
+        let missing = flag.find(&FeatureFlag::Time {
+            name: "testing".to_string(),
+            target: 0.0,
+            enabled: true,
+        });
+
+        assert_eq!(FeatureFlag::Empty, missing);
+    }
+
+    #[test]
+    fn undecodable_redis_fields_are_kept_as_invalid_gates() {
+        let mut raw = RawOptionalFeatureFlags::default();
+        for (field, value) in &[
+            ("boolean", "true"),
+            ("actor/user-1", "false"),
+            ("group/admins", "true"),
+            ("percentage", "time/0.25"),
+            ("actor/user-2", "maybe"),
+            ("percentage", "days/0.5"),
+            ("unknown", "true"),
+        ] {
+            raw.add(RawOptionalFeatureFlag {
+                flag_name: None,
+                field: field.to_string(),
+                value: value.to_string(),
+            });
+        }
+        raw.set_flag_name(String::from("testing"));
+
+        let flag = Flag::try_from(raw).unwrap();
+
+        assert_eq!(4, flag.gates.len());
+        assert!(!flag.enabled_for(&"user-1"));
+        assert!(flag.enabled_for(&"user-2"));
+        assert_eq!(
+            vec!["actor/user-2", "percentage", "unknown"],
+            flag.invalid_gates
+                .iter()
+                .map(|gate| gate.gate_type.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn decode_policy_decides_about_invalid_gates() {
+        let flag = Flag::decode(
+            "testing",
+            vec![
+                Ok(FeatureFlag::Boolean {
+                    name: String::from("testing"),
+                    enabled: true,
+                }),
+                Err(Error::Decode {
+                    flag: String::from("testing"),
+                    gate_type: String::from("percentage"),
+                    raw: String::from("time/abc"),
+                }),
+            ],
+        )
+        .unwrap();
+
+        assert!(flag.enabled());
+        assert!(flag.check_decoded(DecodePolicy::Skip).is_ok());
+        assert!(flag.check_decoded(DecodePolicy::Log).is_ok());
+        match flag.check_decoded(DecodePolicy::Fail) {
+            Err(Error::Decode { raw, .. }) => assert_eq!("time/abc", raw),
+            other => panic!("expected a decode error, got {:?}", other),
+        }
+
+        assert!(Flag::decode(
+            "testing",
+            vec![Err(Error::Custom(String::from("connection refused")))]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn validate_rejects_invalid_gates() {
+        use crate::error::{Field, ValidationError};
+
+        let time = |target: f64, enabled: bool| FeatureFlag::Time {
+            name: String::from("testing"),
+            target,
+            enabled,
+        };
+        let validation = |gate: FeatureFlag| match gate.validate() {
+            Err(Error::Validation(e)) => e,
+            other => panic!("expected a validation error, got {:?}", other),
+        };
+
+        assert!(time(0.5, true).validate().is_ok());
+        assert!(time(0.0, false).validate().is_ok());
+        for target in [0.0, 1.0, 1.7, -0.2, f64::NAN] {
+            match validation(time(target, true)) {
+                ValidationError::Percentage { flag, .. } => assert_eq!("testing", flag),
+                other => panic!("expected a percentage error, got {:?}", other),
+            }
+        }
+
+        assert_eq!(
+            ValidationError::Empty {
+                field: Field::FlagName
+            },
+            validation(FeatureFlag::Boolean {
+                name: String::new(),
+                enabled: true,
+            })
+        );
+        assert_eq!(
+            ValidationError::Empty {
+                field: Field::ActorId
+            },
+            validation(FeatureFlag::Actor {
+                name: String::from("testing"),
+                target: String::new(),
+                enabled: true,
+            })
+        );
+        assert_eq!(
+            ValidationError::NoGate {
+                flag: String::new()
+            },
+            validation(FeatureFlag::Empty)
+        );
     }
 }
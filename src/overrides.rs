@@ -0,0 +1,353 @@
+//! Overrides that are checked before the backend, to force flags without changing the shared storage.
+//!
+//! There are two layers, checked in this order:
+//!
+//! - `FWF_FLAG_<NAME>=true` or `false` environment variables set the boolean gate of the lowercased flag name,
+//!   `FWF_FLAG_NEW_CHECKOUT=false` turns off `new_checkout` for everyone.
+//! - the file from `[overrides] file`, like `.fwf-overrides.toml`, sets boolean, actor and group gates:
+//!
+//! ```toml
+//! [new_checkout]
+//! boolean = true
+//! actors = { "user-1" = false }
+//! groups = { admins = true }
+//! ```
+//!
+//! A layer decides when it has a gate that applies, otherwise the next layer and finally the backend is checked.
+//! The `Evaluation` returned by `evaluate` and `evaluate_for` tells which layer that was.
+
+use crate::config::{utf8_vars, ConfigError, OverridesConfig};
+use crate::models::GroupSet;
+use crate::{Actor, Error, FeatureFlag, Flag, Group};
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// prefix of the environment variables that override the boolean gate
+pub const ENV_PREFIX: &str = "FWF_FLAG_";
+
+/// layer that decided whether a flag is enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    /// a `FWF_FLAG_<NAME>` environment variable
+    Env,
+    /// the overrides file
+    File,
+    /// the gates stored in the backend
+    Backend,
+}
+
+/// whether a flag is enabled and which layer decided that
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Evaluation {
+    pub enabled: bool,
+    pub layer: Layer,
+}
+
+/// the override layers of a client, empty unless they are loaded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Overrides {
+    env: HashMap<String, Flag>,
+    file: HashMap<String, Flag>,
+}
+
+/// a flag as it is written in the overrides file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OverrideFlag {
+    boolean: Option<bool>,
+    #[serde(default)]
+    actors: BTreeMap<String, bool>,
+    #[serde(default)]
+    groups: BTreeMap<String, bool>,
+}
+
+impl Overrides {
+    /// no overrides, every flag is checked in the backend
+    pub fn new() -> Overrides {
+        Overrides::default()
+    }
+
+    /// loads the layers that are enabled in `[overrides]`, by default only the environment,
+    /// the file is only read when `file` is set
+    pub fn load(config: &OverridesConfig) -> Result<Overrides, Error> {
+        let mut overrides = Overrides::new();
+
+        if config.env.unwrap_or(true) {
+            overrides = overrides.with_env();
+        }
+
+        match &config.file {
+            Some(path) => overrides.with_file(path),
+            None => Ok(overrides),
+        }
+    }
+
+    /// reads the `FWF_FLAG_<NAME>` environment variables, variables that are not valid UTF-8
+    /// or not a boolean are skipped
+    pub fn with_env(mut self) -> Overrides {
+        self.env = env_flags(utf8_vars());
+        self
+    }
+
+    /// reads the overrides file, fails if it can not be read or parsed
+    pub fn with_file<P: AsRef<Path>>(mut self, path: P) -> Result<Overrides, Error> {
+        let path = path.as_ref();
+        let file_error =
+            |message: String| ConfigError::Message(format!("{}: {}", path.display(), message));
+
+        let content = std::fs::read_to_string(path).map_err(|e| file_error(e.to_string()))?;
+        self.file = file_flags(&content).map_err(file_error)?;
+        if !self.file.is_empty() {
+            log::warn!(
+                "{} overrides {} flags, they are checked before the backend",
+                path.display(),
+                self.file.len()
+            );
+        }
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.env.is_empty() && self.file.is_empty()
+    }
+
+    /// the override of the flag, `None` if no layer has a boolean gate for it
+    pub fn evaluate(&self, flag_name: &str) -> Option<Evaluation> {
+        self.layers()
+            .find_map(|(layer, flags)| Some((layer, flags.get(flag_name)?.boolean_gate()?)))
+            .map(|(layer, enabled)| Evaluation { enabled, layer })
+    }
+
+    /// the override of the flag for the actor, `None` if no layer has a gate that applies to the actor,
+    /// a disabled boolean override applies to every actor
+    pub fn evaluate_for<T: Actor + Group>(&self, flag_name: &str, actor: &T) -> Option<Evaluation> {
+        self.layers()
+            .find_map(|(layer, flags)| {
+                let flag = flags.get(flag_name)?;
+                Some((layer, flag.gate_for(actor).or_else(|| flag.boolean_gate())?))
+            })
+            .map(|(layer, enabled)| Evaluation { enabled, layer })
+    }
+
+    fn layers(&self) -> impl Iterator<Item = (Layer, &HashMap<String, Flag>)> {
+        vec![(Layer::Env, &self.env), (Layer::File, &self.file)].into_iter()
+    }
+}
+
+/// a variable with a value that is not a boolean is logged and skipped, so a typo only loses that override
+fn env_flags<I: IntoIterator<Item = (String, String)>>(vars: I) -> HashMap<String, Flag> {
+    let mut flags = HashMap::new();
+
+    for (key, value) in vars {
+        let name = match key.strip_prefix(ENV_PREFIX) {
+            Some(name) if !name.is_empty() => name.to_lowercase(),
+            _ => continue,
+        };
+
+        let enabled = match value.to_lowercase().as_str() {
+            "true" | "1" => true,
+            "false" | "0" => false,
+            _ => {
+                log::warn!(
+                    "{} must be true or false, got '{}', the override is skipped",
+                    key,
+                    value
+                );
+                continue;
+            }
+        };
+
+        let gate = FeatureFlag::Boolean {
+            name: name.clone(),
+            enabled,
+        };
+        flags.insert(name.clone(), Flag::new(name, vec![gate]));
+    }
+
+    flags
+}
+
+fn file_flags(content: &str) -> Result<HashMap<String, Flag>, String> {
+    let override_flags: BTreeMap<String, OverrideFlag> =
+        toml::from_str(content).map_err(|e| e.to_string())?;
+
+    Ok(override_flags
+        .into_iter()
+        .map(|(name, override_flag)| {
+            let flag = to_flag(&name, override_flag);
+            (name, flag)
+        })
+        .collect())
+}
+
+fn to_flag(name: &str, override_flag: OverrideFlag) -> Flag {
+    let mut gates = Vec::new();
+
+    if let Some(enabled) = override_flag.boolean {
+        gates.push(FeatureFlag::Boolean {
+            name: name.to_string(),
+            enabled,
+        });
+    }
+
+    for (actor_id, enabled) in override_flag.actors {
+        gates.push(FeatureFlag::Actor {
+            name: name.to_string(),
+            target: actor_id,
+            enabled,
+        });
+    }
+
+    for (group_name, enabled) in override_flag.groups {
+        gates.push(FeatureFlag::Group {
+            name: name.to_string(),
+            target: GroupSet::new(group_name),
+            enabled,
+        });
+    }
+
+    Flag::new(name, gates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{env_flags, file_flags, Evaluation, Layer, Overrides};
+    use crate::{Actor, Group};
+
+    struct Admin;
+
+    impl Actor for Admin {
+        fn feature_flag_id(&self) -> String {
+            String::from("admin-1")
+        }
+    }
+
+    impl Group for Admin {
+        fn is_in_group(&self, group_name: &str) -> bool {
+            group_name == "admins"
+        }
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn env_overrides_the_boolean_gate() {
+        let env = env_flags(vars(&[
+            ("FWF_FLAG_NEW_CHECKOUT", "true"),
+            ("FWF_FLAG_KILL_SWITCH", "0"),
+            ("DATABASE_NAME", "0"),
+        ]));
+        let overrides = Overrides {
+            env,
+            ..Overrides::default()
+        };
+
+        assert_eq!(
+            Some(Evaluation {
+                enabled: true,
+                layer: Layer::Env
+            }),
+            overrides.evaluate("new_checkout")
+        );
+        assert_eq!(
+            Some(false),
+            overrides.evaluate("kill_switch").map(|e| e.enabled)
+        );
+        assert_eq!(None, overrides.evaluate("other"));
+
+        let env = env_flags(vars(&[
+            ("FWF_FLAG_NEW_CHECKOUT", "yes"),
+            ("FWF_FLAG_KILL_SWITCH", "false"),
+        ]));
+        assert_eq!(vec!["kill_switch"], env.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn env_is_checked_before_the_file() {
+        let overrides = Overrides {
+            env: env_flags(vars(&[("FWF_FLAG_NEW_CHECKOUT", "false")])),
+            file: file_flags(
+                r#"
+[new_checkout]
+actors = { "admin-1" = true }
+
+[beta]
+boolean = false
+groups = { admins = true }
+"#,
+            )
+            .unwrap(),
+        };
+
+        assert_eq!(
+            Some(Layer::Env),
+            overrides
+                .evaluate_for("new_checkout", &Admin)
+                .map(|e| e.layer)
+        );
+        assert_eq!(
+            Some(Evaluation {
+                enabled: true,
+                layer: Layer::File
+            }),
+            overrides.evaluate_for("beta", &Admin)
+        );
+        assert_eq!(
+            Some(false),
+            overrides.evaluate_for("beta", &"user-1").map(|e| e.enabled)
+        );
+    }
+
+    #[test]
+    fn file_without_deciding_gate_falls_through() {
+        let overrides = Overrides {
+            file: file_flags("[beta]\nactors = { \"admin-1\" = true }\n").unwrap(),
+            ..Overrides::default()
+        };
+
+        assert_eq!(None, overrides.evaluate("beta"));
+        assert_eq!(None, overrides.evaluate_for("beta", &"user-1"));
+        assert!(file_flags("[beta]\npercentage_of_time = 0.5\n").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn env_that_is_not_utf8_is_skipped() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let key = OsStr::from_bytes(b"FWF_FLAG_NOT_UTF8_\xff");
+        std::env::set_var(key, OsStr::from_bytes(b"\xff"));
+        let overrides = Overrides::new().with_env();
+        let config = crate::config::fetch_config();
+        std::env::remove_var(key);
+
+        assert_eq!(None, overrides.evaluate("not_utf8_\u{fffd}"));
+        assert!(config.is_ok());
+    }
+
+    #[test]
+    fn file_is_only_read_when_it_is_configured() {
+        use crate::config::OverridesConfig;
+
+        let path =
+            std::env::temp_dir().join(format!("fun-with-flags-{}-opt-in.toml", std::process::id()));
+        std::fs::write(&path, "[beta]\nboolean = true\n").unwrap();
+
+        let without_env = |file: Option<String>| OverridesConfig {
+            env: Some(false),
+            file,
+        };
+        let unset = Overrides::load(&without_env(None)).unwrap();
+        let set = Overrides::load(&without_env(Some(path.display().to_string()))).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(unset.is_empty());
+        assert_eq!(Some(Layer::File), set.evaluate("beta").map(|e| e.layer));
+    }
+}
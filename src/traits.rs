@@ -7,7 +7,7 @@ where
     T: AsRef<str>,
 {
     fn feature_flag_id(&self) -> String {
-        format!("{}", self.as_ref())
+        self.as_ref().to_string()
     }
 }
 
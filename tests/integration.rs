@@ -1,3 +1,5 @@
+#![allow(clippy::bool_assert_comparison)]
+
 use serial_test::serial;
 
 struct Person {
@@ -20,16 +22,11 @@ impl fun_with_flags::Group for Person {
     }
 }
 
-#[cfg(feature = "postgres-backend")]
+#[cfg(all(feature = "postgres-backend", not(feature = "redis-backend")))]
 mod postgres_test_context {
-    use fun_with_flags;
-    use postgres::NoTls;
-
-    use r2d2_postgres::PostgresConnectionManager;
-
-    type PostgresClient = r2d2::PooledConnection<PostgresConnectionManager<NoTls>>;
+    use postgres::{Client, NoTls};
 
-    const MAIN_DATABASE: &'static str = "postgres";
+    const MAIN_DATABASE: &str = "postgres";
 
     pub struct TestContext {
         is_dropped: bool,
@@ -38,12 +35,12 @@ mod postgres_test_context {
     impl TestContext {
         pub fn new() -> Self {
             println!("Set up resources");
-            let conn = fun_with_flags::establish_connection_to_database(MAIN_DATABASE);
-            Self::create_db(&conn);
+            let mut client = Self::get_client(MAIN_DATABASE);
+            Self::create_db(&mut client);
 
-            let conn = fun_with_flags::establish_connection().unwrap();
+            let mut client = Self::get_client("fun_with_flags_repo");
 
-            Self::migrate(&conn);
+            Self::migrate(&mut client);
             println!("Set up resources, done");
 
             Self { is_dropped: false }
@@ -51,43 +48,41 @@ mod postgres_test_context {
 
         fn clean(&mut self) {
             println!("Clean up resources");
-            let conn = fun_with_flags::establish_connection_to_database(MAIN_DATABASE);
-            Self::drop_db(&conn);
+            fun_with_flags::default_client()
+                .backend()
+                .clear_all()
+                .unwrap();
 
-            cfg_if::cfg_if! {
-                if #[cfg(feature = "cached")] {
-                    fun_with_flags::backend::cached::flush_cache()
-                }
-            }
+            let mut client = Self::get_client(MAIN_DATABASE);
+            Self::drop_db(&mut client);
 
             self.is_dropped = true;
         }
 
-        fn get_client(conn: &fun_with_flags::DBConnection) -> PostgresClient {
-            fun_with_flags::Backend::create_conn(conn).unwrap()
-        }
+        fn get_client(database_name: &str) -> Client {
+            let config = fun_with_flags::config::fetch_config().unwrap();
+            let (database_url, _name) = config.parts().expect("DATABASE_ADDRESS must be set");
 
-        fn drop_db(conn: &fun_with_flags::DBConnection) {
-            let mut client = Self::get_client(conn);
+            Client::connect(&format!("{}/{}", database_url, database_name), NoTls).unwrap()
+        }
 
-            client.batch_execute("SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = 'fun_with_flags_repo';")
-                .unwrap();
+        fn drop_db(client: &mut Client) {
+            // the pools and listeners of the backend can reconnect before the database is dropped,
+            // FORCE terminates their connections in the same statement
             client
-                .batch_execute("DROP DATABASE fun_with_flags_repo")
+                .batch_execute("DROP DATABASE fun_with_flags_repo WITH (FORCE)")
                 .expect("Couldn't drop database fun_with_flags_repo");
         }
 
-        fn create_db(conn: &fun_with_flags::DBConnection) {
-            if Self::database_not_exists(conn) {
-                let mut client = Self::get_client(conn);
+        fn create_db(client: &mut Client) {
+            if Self::database_not_exists(client) {
                 client
                     .batch_execute("CREATE DATABASE fun_with_flags_repo;")
                     .unwrap();
             }
         }
 
-        fn database_not_exists(conn: &fun_with_flags::DBConnection) -> bool {
-            let mut client = Self::get_client(conn);
+        fn database_not_exists(client: &mut Client) -> bool {
             client
                 .query(
                     "SELECT 1 as column from pg_database where datname = 'fun_with_flags_repo';",
@@ -97,19 +92,19 @@ mod postgres_test_context {
                 .is_empty()
         }
 
-        fn table_not_exists(conn: &fun_with_flags::DBConnection) -> bool {
-            let mut client = Self::get_client(conn);
+        fn table_not_exists(client: &mut Client) -> bool {
             client
                 .query("SELECT 1 as column FROM information_schema.tables WHERE table_name = 'fun_with_flags_toggles';", &[])
                 .unwrap()
                 .is_empty()
         }
 
-        fn migrate(conn: &fun_with_flags::DBConnection) {
-            let mut client = Self::get_client(conn);
+        fn migrate(client: &mut Client) {
             let migration = include_str!("../migrations/postgres/up.sql");
-            if Self::table_not_exists(conn) {
+            let notify_migration = include_str!("../migrations/postgres_notify/up.sql");
+            if Self::table_not_exists(client) {
                 client.batch_execute(migration).unwrap();
+                client.batch_execute(notify_migration).unwrap();
             }
         }
     }
@@ -129,9 +124,56 @@ mod redis_test_context {
 
     impl TestContext {
         pub fn new() -> Self {
-            let db = fun_with_flags::establish_connection().unwrap();
+            fun_with_flags::default_client()
+                .backend()
+                .clear_all()
+                .unwrap();
 
-            fun_with_flags::Backend::clean_all(&db).unwrap();
+            TestContext {}
+        }
+    }
+
+    impl Drop for TestContext {
+        fn drop(&mut self) {
+            fun_with_flags::default_client()
+                .backend()
+                .clear_all()
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(all(
+    feature = "sqlite-backend",
+    not(any(feature = "redis-backend", feature = "postgres-backend"))
+))]
+mod sqlite_test_context {
+    use rusqlite::Connection;
+
+    pub struct TestContext;
+
+    impl TestContext {
+        pub fn new() -> Self {
+            let config = fun_with_flags::config::fetch_config().unwrap();
+            let url = config.to_url().expect("DATABASE_ADDRESS must be set");
+            let conn = Connection::open(url.trim_start_matches("sqlite://")).unwrap();
+
+            let table_not_exists: bool = conn
+                .query_row(
+                    "SELECT count(*) = 0 FROM sqlite_master WHERE type = 'table' AND name = 'fun_with_flags_toggles'",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            if table_not_exists {
+                conn.execute_batch(include_str!("../migrations/sqlite/up.sql"))
+                    .unwrap();
+            }
+
+            fun_with_flags::default_client()
+                .backend()
+                .clear_all()
+                .unwrap();
 
             TestContext {}
         }
@@ -139,26 +181,80 @@ mod redis_test_context {
 
     impl Drop for TestContext {
         fn drop(&mut self) {
-            let db = fun_with_flags::establish_connection().unwrap();
+            fun_with_flags::default_client()
+                .backend()
+                .clear_all()
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(all(
+    feature = "mysql-backend",
+    not(any(
+        feature = "redis-backend",
+        feature = "postgres-backend",
+        feature = "sqlite-backend"
+    ))
+))]
+mod mysql_test_context {
+    use mysql::prelude::Queryable;
 
-            fun_with_flags::Backend::clean_all(&db).unwrap();
+    pub struct TestContext;
 
-            cfg_if::cfg_if! {
-                if #[cfg(feature = "cached")] {
-                    fun_with_flags::backend::cached::flush_cache()
+    impl TestContext {
+        pub fn new() -> Self {
+            let config = fun_with_flags::config::fetch_config().unwrap();
+            let url = config.to_url().expect("DATABASE_ADDRESS must be set");
+            let mut conn = mysql::Conn::new(url.as_str()).unwrap();
+
+            let table_not_exists: Option<bool> = conn
+                .query_first(
+                    "SELECT count(*) = 0 FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name = 'fun_with_flags_toggles'",
+                )
+                .unwrap();
+            if table_not_exists.unwrap_or(true) {
+                for statement in include_str!("../migrations/mysql/up.sql").split(';') {
+                    if !statement.trim().is_empty() {
+                        conn.query_drop(statement).unwrap();
+                    }
                 }
             }
+
+            fun_with_flags::default_client()
+                .backend()
+                .clear_all()
+                .unwrap();
+
+            TestContext {}
+        }
+    }
+
+    impl Drop for TestContext {
+        fn drop(&mut self) {
+            fun_with_flags::default_client()
+                .backend()
+                .clear_all()
+                .unwrap();
         }
     }
 }
 
-mod empty_test_context {
-    #[allow(dead_code)]
+#[cfg(not(any(
+    feature = "redis-backend",
+    feature = "postgres-backend",
+    feature = "sqlite-backend",
+    feature = "mysql-backend"
+)))]
+mod memory_test_context {
+    use fun_with_flags::{FunWithFlags, InMemoryBackend};
+
     pub struct TestContext;
 
     impl TestContext {
-        #[allow(dead_code)]
         pub fn new() -> Self {
+            fun_with_flags::set_default_client(FunWithFlags::with_backend(InMemoryBackend::new()));
+
             TestContext {}
         }
     }
@@ -169,8 +265,12 @@ cfg_if::cfg_if! {
         use redis_test_context::TestContext;
     } else if #[cfg(feature = "postgres-backend")] {
         use postgres_test_context::TestContext;
+    } else if #[cfg(feature = "sqlite-backend")] {
+        use sqlite_test_context::TestContext;
+    } else if #[cfg(feature = "mysql-backend")] {
+        use mysql_test_context::TestContext;
     } else {
-        use empty_test_context::TestContext;
+        use memory_test_context::TestContext;
     }
 }
 
@@ -223,16 +323,12 @@ fn enable_chance() {
     assert_eq!(false, fun_with_flags::enabled(flag_name));
     fun_with_flags::enable_percentage_of_time(flag_name, 0.50).unwrap();
     // chance of getting 40 times false in a row for 50% is very small (0.000000000090949470177292823792%)
-    let result = (0..40)
-        .map(|_x| fun_with_flags::enabled(flag_name))
-        .any(|x| x);
+    let result = (0..40).any(|_x| fun_with_flags::enabled(flag_name));
 
     assert_eq!(true, result);
 
     fun_with_flags::disable_percentage_of_time(flag_name).unwrap();
-    let result = (0..40)
-        .map(|_x| fun_with_flags::enabled(flag_name))
-        .any(|x| x);
+    let result = (0..40).any(|_x| fun_with_flags::enabled(flag_name));
     assert_eq!(false, result);
 }
 
@@ -317,20 +413,339 @@ fn enable_for_group_specific() {
 #[test]
 #[serial]
 fn get_returns_empty_when_flag_not_found() {
-    use fun_with_flags::{Backend, FeatureFlag};
+    use fun_with_flags::FeatureFlag;
 
     let mut _ctx = TestContext::new();
-    let db = fun_with_flags::establish_connection().unwrap();
+    let client = fun_with_flags::default_client();
 
-    let res = Backend::get(
-        &db,
-        FeatureFlag::Time {
+    let res = client
+        .backend()
+        .get(FeatureFlag::Time {
             name: "empty".to_string(),
             enabled: true,
             target: 0.0,
-        },
-    )
-    .unwrap();
+        })
+        .unwrap();
 
     assert_eq!(res, FeatureFlag::Empty)
 }
+
+#[test]
+#[serial]
+fn boolean_gate_applies_to_actors() {
+    let mut _ctx = TestContext::new();
+
+    let flag_name = "precedence_boolean_flag";
+
+    let john = Person {
+        name: String::from("john"),
+    };
+
+    fun_with_flags::enable(flag_name).unwrap();
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &john));
+    fun_with_flags::disable(flag_name).unwrap();
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &john));
+}
+
+#[test]
+#[serial]
+fn disabled_actor_overrides_boolean() {
+    let mut _ctx = TestContext::new();
+
+    let flag_name = "precedence_actor_flag";
+
+    let john = Person {
+        name: String::from("john"),
+    };
+
+    let pete = Person {
+        name: String::from("pete"),
+    };
+
+    fun_with_flags::enable(flag_name).unwrap();
+    fun_with_flags::disable_for(flag_name, &john).unwrap();
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &john));
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &pete));
+    assert_eq!(true, fun_with_flags::enabled(flag_name));
+
+    fun_with_flags::disable(flag_name).unwrap();
+    fun_with_flags::enable_for(flag_name, &john).unwrap();
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &john));
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &pete));
+    assert_eq!(false, fun_with_flags::enabled(flag_name));
+}
+
+#[test]
+#[serial]
+fn disabled_group_overrides_boolean() {
+    let mut _ctx = TestContext::new();
+
+    let flag_name = "precedence_group_flag";
+
+    let john = Person {
+        name: String::from("john"),
+    };
+
+    let pete = Person {
+        name: String::from("pete"),
+    };
+
+    fun_with_flags::enable(flag_name).unwrap();
+    fun_with_flags::disable_for_group(flag_name, "johns-group").unwrap();
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &john));
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &pete));
+
+    fun_with_flags::disable(flag_name).unwrap();
+    fun_with_flags::enable_for_group(flag_name, "johns-group").unwrap();
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &john));
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &pete));
+}
+
+#[test]
+#[serial]
+fn actor_gate_overrides_group() {
+    let mut _ctx = TestContext::new();
+
+    let flag_name = "precedence_actor_group_flag";
+
+    let john = Person {
+        name: String::from("john"),
+    };
+
+    fun_with_flags::disable_for_group(flag_name, "test").unwrap();
+    fun_with_flags::enable_for(flag_name, &john).unwrap();
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &john));
+
+    fun_with_flags::enable_for_group(flag_name, "test").unwrap();
+    fun_with_flags::disable_for(flag_name, &john).unwrap();
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &john));
+}
+
+#[test]
+#[serial]
+fn enabled_boolean_overrides_percentage() {
+    let mut _ctx = TestContext::new();
+
+    let flag_name = "precedence_percentage_flag";
+
+    let pete = Person {
+        name: String::from("pete"),
+    };
+
+    fun_with_flags::enable_percentage_of_actors(flag_name, 0.001).unwrap();
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &pete));
+    fun_with_flags::enable(flag_name).unwrap();
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &pete));
+}
+
+#[test]
+#[serial]
+fn disabled_boolean_falls_through_to_percentage() {
+    let mut _ctx = TestContext::new();
+
+    let actors_flag = "precedence_disabled_actors_flag";
+    let time_flag = "precedence_disabled_time_flag";
+
+    let pete = Person {
+        name: String::from("pete"),
+    };
+
+    fun_with_flags::disable(actors_flag).unwrap();
+    fun_with_flags::enable_percentage_of_actors(actors_flag, 0.999).unwrap();
+    assert_eq!(true, fun_with_flags::enabled_for(actors_flag, &pete));
+
+    fun_with_flags::disable(time_flag).unwrap();
+    fun_with_flags::enable_percentage_of_time(time_flag, 0.999).unwrap();
+    let result = (0..40).any(|_x| fun_with_flags::enabled_for(time_flag, &pete));
+    assert_eq!(true, result);
+}
+
+#[test]
+#[serial]
+fn percentage_of_time_applies_to_actors() {
+    let mut _ctx = TestContext::new();
+
+    let flag_name = "precedence_time_flag";
+
+    let john = Person {
+        name: String::from("john"),
+    };
+
+    fun_with_flags::enable_percentage_of_time(flag_name, 0.50).unwrap();
+    let result = (0..40).any(|_x| fun_with_flags::enabled_for(flag_name, &john));
+    assert_eq!(true, result);
+
+    fun_with_flags::disable_percentage_of_time(flag_name).unwrap();
+    let result = (0..40).any(|_x| fun_with_flags::enabled_for(flag_name, &john));
+    assert_eq!(false, result);
+}
+
+#[test]
+#[serial]
+fn enable_for_multiple_groups() {
+    use fun_with_flags::FeatureFlag;
+
+    let mut _ctx = TestContext::new();
+
+    let flag_name = "multiple_groups_flag";
+
+    let john = Person {
+        name: String::from("john"),
+    };
+
+    let pete = Person {
+        name: String::from("pete"),
+    };
+
+    fun_with_flags::enable_for_group(flag_name, "test").unwrap();
+    fun_with_flags::disable_for_group(flag_name, "johns-group").unwrap();
+
+    // john is in both groups, the disabled group wins
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &john));
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &pete));
+
+    let flag = fun_with_flags::default_client()
+        .backend()
+        .get_flag(flag_name)
+        .unwrap();
+    let groups = flag
+        .gates
+        .iter()
+        .filter(|gate| matches!(gate, FeatureFlag::Group { .. }))
+        .count();
+    assert_eq!(2, groups);
+
+    fun_with_flags::enable_for_group(flag_name, "johns-group").unwrap();
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &john));
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &pete));
+}
+
+#[test]
+#[serial]
+fn list_all_flags() {
+    use fun_with_flags::FeatureFlag;
+
+    let mut _ctx = TestContext::new();
+
+    let john = Person {
+        name: String::from("john"),
+    };
+
+    assert!(fun_with_flags::all_flag_names().unwrap().is_empty());
+    assert!(fun_with_flags::all_flags().unwrap().is_empty());
+    assert!(fun_with_flags::get_flag("list_a").unwrap().is_empty());
+
+    fun_with_flags::enable("list_a").unwrap();
+    fun_with_flags::enable_for("list_a", &john).unwrap();
+    fun_with_flags::enable_percentage_of_actors("list_b", 0.5).unwrap();
+
+    let names = fun_with_flags::all_flag_names().unwrap();
+    assert_eq!(2, names.len());
+    assert!(names.contains("list_a"));
+    assert!(names.contains("list_b"));
+
+    let flags = fun_with_flags::all_flags().unwrap();
+    assert_eq!(2, flags.len());
+    assert_eq!("list_a", flags[0].name);
+    assert_eq!(2, flags[0].gates.len());
+    assert_eq!("list_b", flags[1].name);
+    assert_eq!(
+        vec![FeatureFlag::Percentage {
+            name: "list_b".to_string(),
+            target: 0.5,
+            enabled: true,
+        }],
+        flags[1].gates
+    );
+
+    assert_eq!(flags[0], fun_with_flags::get_flag("list_a").unwrap());
+}
+
+#[test]
+#[serial]
+fn clear_gates() {
+    let mut _ctx = TestContext::new();
+
+    let flag_name = "clear_flag";
+
+    let john = Person {
+        name: String::from("john"),
+    };
+
+    let pete = Person {
+        name: String::from("pete"),
+    };
+
+    fun_with_flags::enable(flag_name).unwrap();
+    fun_with_flags::disable_for(flag_name, &john).unwrap();
+    fun_with_flags::disable_for_group(flag_name, "test").unwrap();
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &john));
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &pete));
+
+    fun_with_flags::clear_for(flag_name, &john).unwrap();
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &john));
+    fun_with_flags::clear_for_group(flag_name, "test").unwrap();
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &john));
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &pete));
+
+    fun_with_flags::enable_percentage_of_actors(flag_name, 0.001).unwrap();
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &pete));
+    fun_with_flags::clear_boolean(flag_name).unwrap();
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &pete));
+
+    fun_with_flags::enable_percentage_of_actors(flag_name, 0.999).unwrap();
+    assert_eq!(true, fun_with_flags::enabled_for(flag_name, &pete));
+    fun_with_flags::clear_percentage(flag_name).unwrap();
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &pete));
+
+    assert!(fun_with_flags::get_flag(flag_name).unwrap().is_empty());
+    assert!(!fun_with_flags::all_flag_names()
+        .unwrap()
+        .contains(flag_name));
+}
+
+#[test]
+#[serial]
+fn clear_flag() {
+    let mut _ctx = TestContext::new();
+
+    let flag_name = "clear_all_gates_flag";
+
+    let john = Person {
+        name: String::from("john"),
+    };
+
+    fun_with_flags::enable(flag_name).unwrap();
+    fun_with_flags::enable_for(flag_name, &john).unwrap();
+    fun_with_flags::enable_for_group(flag_name, "test").unwrap();
+    assert_eq!(true, fun_with_flags::enabled(flag_name));
+
+    fun_with_flags::clear(flag_name).unwrap();
+    assert_eq!(false, fun_with_flags::enabled(flag_name));
+    assert_eq!(false, fun_with_flags::enabled_for(flag_name, &john));
+    assert!(fun_with_flags::get_flag(flag_name).unwrap().is_empty());
+}
+
+#[test]
+#[serial]
+#[cfg(all(
+    feature = "postgres-backend",
+    feature = "cached",
+    not(feature = "redis-backend")
+))]
+fn change_of_other_client_evicts_cached_flag() {
+    let mut _ctx = TestContext::new();
+
+    let flag_name = "notified_flag";
+    let other = fun_with_flags::FunWithFlags::new().unwrap();
+
+    assert_eq!(false, fun_with_flags::enabled(flag_name));
+    other.enable(flag_name).unwrap();
+
+    // the notification arrives on a background thread, without it the flag stays cached for a minute
+    let result = (0..50).any(|_x| {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        fun_with_flags::enabled(flag_name)
+    });
+    assert_eq!(true, result);
+}
@@ -0,0 +1,105 @@
+#![cfg(all(feature = "async", feature = "postgres-backend"))]
+
+use fun_with_flags::asynchronous::AsyncFunWithFlags;
+use tokio_postgres::NoTls;
+
+const DATABASE: &str = "fun_with_flags_async_repo";
+
+struct Person {
+    name: String,
+}
+
+impl fun_with_flags::Actor for Person {
+    fn feature_flag_id(&self) -> String {
+        format!("person-{}", self.name)
+    }
+}
+
+impl fun_with_flags::Group for Person {
+    fn is_in_group(&self, group_name: &str) -> bool {
+        group_name == "test"
+    }
+}
+
+async fn execute(database_name: &str, query: &str) {
+    let config = fun_with_flags::config::fetch_config().unwrap();
+    let (database_url, _name) = config.parts().expect("DATABASE_ADDRESS must be set");
+
+    let (client, connection) =
+        tokio_postgres::connect(&format!("{}/{}", database_url, database_name), NoTls)
+            .await
+            .unwrap();
+    tokio::spawn(connection);
+
+    client.batch_execute(query).await.unwrap();
+}
+
+async fn setup() -> AsyncFunWithFlags {
+    execute("postgres", &format!("DROP DATABASE IF EXISTS {}", DATABASE)).await;
+    execute("postgres", &format!("CREATE DATABASE {}", DATABASE)).await;
+    execute(DATABASE, include_str!("../migrations/postgres/up.sql")).await;
+
+    let config = fun_with_flags::config::fetch_config().unwrap();
+    let (database_url, _name) = config.parts().unwrap();
+
+    AsyncFunWithFlags::builder()
+        .url(format!("{}/{}", database_url, DATABASE))
+        .build()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn async_postgres_roundtrip() {
+    let client = setup().await;
+    let person = Person {
+        name: String::from("john"),
+    };
+
+    assert!(!client.enabled("async_boolean").await);
+    assert!(client.enable("async_boolean").await.is_ok());
+    assert!(client.enabled("async_boolean").await);
+    assert!(client.disable("async_boolean").await.is_ok());
+    assert!(!client.enabled("async_boolean").await);
+
+    assert!(client.enable_for("async_actor", &person).await.is_ok());
+    assert!(client.enabled_for("async_actor", &person).await);
+
+    assert!(client.enable_for_group("async_group", "test").await.is_ok());
+    assert!(client.enabled_for("async_group", &person).await);
+
+    assert!(client
+        .enable_percentage_of_time("async_time", 0.99)
+        .await
+        .is_ok());
+    assert!(client
+        .disable_percentage_of_time("async_time")
+        .await
+        .is_ok());
+    assert!(!client.enabled("async_time").await);
+
+    let flags = client.all_flags().await.unwrap();
+    assert_eq!(4, flags.len());
+    assert_eq!(flags[0], client.get_flag("async_actor").await.unwrap());
+
+    let mut names: Vec<String> = client.all_flag_names().await.unwrap().into_iter().collect();
+    names.sort();
+    assert_eq!(
+        vec!["async_actor", "async_boolean", "async_group", "async_time"],
+        names
+    );
+
+    client.backend().clear_all().await.unwrap();
+    assert!(client.backend().list().await.unwrap().is_empty());
+
+    drop(client);
+    execute(
+        "postgres",
+        &format!(
+            "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = '{}'",
+            DATABASE
+        ),
+    )
+    .await;
+    execute("postgres", &format!("DROP DATABASE {}", DATABASE)).await;
+}